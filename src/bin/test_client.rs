@@ -36,7 +36,7 @@ async fn main() -> io::Result<()> {
     info!("Received {msg_size} bytes");
 
     let reply = Message::parse(buff).unwrap();
-    info!("Get reply {:?}", reply);
+    info!("Get reply\n{reply}");
 
     Ok(())
 }
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use static_init::dynamic;
+
+use crate::common::{DNSServer, FlagRCode, LabelSeq};
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::rrset::RRset;
+
+pub const MAX_CACHE_TTL: u32 = 86400;
+pub const MAX_CACHE_ENTRIES: usize = 10000;
+
+#[derive(Debug, Clone)]
+pub enum CachedAnswer {
+    Positive(Vec<RRset>),
+    /// Name does not exist, holds the SOA of the zone
+    NxDomain(ResourceRecord),
+    /// Name exists but has no record of requested type, holds the SOA of the zone
+    NoData(ResourceRecord),
+}
+
+impl CachedAnswer {
+    /// Build a negative answer from an authority response as per RFC 2308
+    ///
+    /// Return `None` if the response is not negative or does not carry an SOA
+    pub fn negative_from(msg: &Message) -> Option<CachedAnswer> {
+        let rcode = msg.header.get_rcode().ok();
+        let is_nxdomain = rcode == Some(FlagRCode::NXDOMAIN);
+        let is_nodata = rcode == Some(FlagRCode::NOERROR)
+            && msg.resources.is_empty()
+            && !msg
+                .auth_resources
+                .iter()
+                .any(|r| matches!(r.data, ResourceData::NS(_)));
+
+        if !is_nxdomain && !is_nodata {
+            return None;
+        }
+
+        let soa = msg
+            .auth_resources
+            .iter()
+            .find(|r| matches!(r.data, ResourceData::SOA(_)))?
+            .clone();
+
+        if is_nxdomain {
+            Some(CachedAnswer::NxDomain(soa))
+        } else {
+            Some(CachedAnswer::NoData(soa))
+        }
+    }
+
+    pub fn to_message(&self, question: Question) -> Message {
+        let mut msg = Message::new();
+        msg.add_question(question);
+
+        match self {
+            CachedAnswer::Positive(rrsets) => {
+                for rrset in rrsets {
+                    msg.add_rrset(rrset);
+                }
+            }
+            CachedAnswer::NxDomain(soa) => {
+                msg.header.set_rcode(FlagRCode::NXDOMAIN);
+                msg.add_auth_resource(soa.clone());
+            }
+            CachedAnswer::NoData(soa) => {
+                msg.add_auth_resource(soa.clone());
+            }
+        }
+
+        msg
+    }
+
+    /// Number of seconds this answer can be cached
+    fn ttl(&self) -> u32 {
+        let ttl = match self {
+            CachedAnswer::Positive(rrsets) => rrsets.iter().map(|r| r.ttl).min().unwrap_or(0),
+            CachedAnswer::NxDomain(soa) | CachedAnswer::NoData(soa) => match &soa.data {
+                ResourceData::SOA(data) => soa.ttl.min(data.minimum),
+                _ => 0,
+            },
+        };
+        ttl.min(MAX_CACHE_TTL)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    answer: CachedAnswer,
+    /// Answer was validated with DNSSEC and found secure
+    authenticated: bool,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+impl CacheEntry {
+    fn new(answer: CachedAnswer, authenticated: bool) -> CacheEntry {
+        let inserted_at = Instant::now();
+        let ttl = answer.ttl();
+
+        CacheEntry {
+            answer,
+            authenticated,
+            inserted_at,
+            expires_at: inserted_at + Duration::from_secs(ttl as u64),
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Copy of the answer with TTL decreased by the time spent in cache
+    fn answer_at(&self, now: Instant) -> CachedAnswer {
+        match &self.answer {
+            CachedAnswer::Positive(rrsets) => {
+                let elapsed = now.duration_since(self.inserted_at).as_secs() as u32;
+                let rrsets = rrsets
+                    .iter()
+                    .map(|r| {
+                        let mut r = r.clone();
+                        r.ttl = r.ttl.min(MAX_CACHE_TTL).saturating_sub(elapsed);
+                        r
+                    })
+                    .collect();
+                CachedAnswer::Positive(rrsets)
+            }
+            CachedAnswer::NxDomain(soa) | CachedAnswer::NoData(soa) => {
+                // RFC 2308 section 5: the SOA TTL is the remaining negative TTL
+                let mut soa = soa.clone();
+                soa.ttl = self.expires_at.duration_since(now).as_secs() as u32;
+                if let CachedAnswer::NxDomain(_) = self.answer {
+                    CachedAnswer::NxDomain(soa)
+                } else {
+                    CachedAnswer::NoData(soa)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DelegationEntry {
+    servers: Vec<DNSServer>,
+    expires_at: Instant,
+}
+
+/// Cache shared by all requests
+///
+/// Answers are keyed by question.
+/// A positive entry lives as long as the smallest TTL among its RRsets,
+/// since the answer is incomplete once any of them expired.
+/// A negative entry lives for the lesser of the SOA TTL and the SOA minimum field.
+///
+/// Delegations are keyed by zone cut and hold the name servers of that zone
+/// together with their glue addresses, if any
+pub struct Cache {
+    answers: Mutex<HashMap<Question, CacheEntry>>,
+    delegations: Mutex<HashMap<LabelSeq, DelegationEntry>>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            answers: Mutex::new(HashMap::new()),
+            delegations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cached answer, and whether it was authenticated
+    pub fn get(&self, question: &Question) -> Option<(CachedAnswer, bool)> {
+        let now = Instant::now();
+        let mut answers = self.answers.lock().unwrap();
+
+        let entry = answers.get(question)?;
+        if entry.is_expired(now) {
+            debug!("Cache entry expired: {:?}", question);
+            answers.remove(question);
+            return None;
+        }
+
+        Some((entry.answer_at(now), entry.authenticated))
+    }
+
+    pub fn insert(&self, question: Question, answer: CachedAnswer, authenticated: bool) {
+        let entry = CacheEntry::new(answer, authenticated);
+        let now = Instant::now();
+        if entry.is_expired(now) {
+            return;
+        }
+
+        let mut answers = self.answers.lock().unwrap();
+        if !answers.contains_key(&question) {
+            make_room(&mut answers, |e| e.expires_at, now);
+        }
+        answers.insert(question, entry);
+    }
+
+    pub fn remove(&self, question: &Question) {
+        self.answers.lock().unwrap().remove(question);
+    }
+
+    /// Find the deepest cached zone cut that `name` belongs to
+    pub fn closest_delegation(&self, name: &LabelSeq) -> Option<(LabelSeq, Vec<DNSServer>)> {
+        let now = Instant::now();
+        let mut delegations = self.delegations.lock().unwrap();
+
+        for i in 0..name.labels.len() {
+            let zone = LabelSeq {
+                labels: name.labels[i..].to_vec(),
+            };
+            let Some(entry) = delegations.get(&zone) else {
+                continue;
+            };
+            if now >= entry.expires_at {
+                debug!("Delegation expired: {:?}", zone);
+                delegations.remove(&zone);
+                continue;
+            }
+            let servers = entry.servers.clone();
+            return Some((zone, servers));
+        }
+
+        None
+    }
+
+    pub fn insert_delegation(&self, zone: LabelSeq, servers: Vec<DNSServer>, ttl: u32) {
+        let ttl = ttl.min(MAX_CACHE_TTL);
+        if servers.is_empty() || ttl == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let entry = DelegationEntry {
+            servers,
+            expires_at: now + Duration::from_secs(ttl as u64),
+        };
+
+        let mut delegations = self.delegations.lock().unwrap();
+        if !delegations.contains_key(&zone) {
+            make_room(&mut delegations, |e| e.expires_at, now);
+        }
+        delegations.insert(zone, entry);
+    }
+
+    pub fn clear(&self) {
+        self.answers.lock().unwrap().clear();
+        self.delegations.lock().unwrap().clear();
+    }
+}
+
+/// Make room for one more entry in `map`, which holds at most `MAX_CACHE_ENTRIES`
+///
+/// Expired entries go first. When that is not enough, the entries closest to expiring
+/// go as well, down to nine tenths of the limit, so that the inserts that follow do not
+/// scan the map again
+pub fn make_room<K: Clone + Eq + Hash, V>(
+    map: &mut HashMap<K, V>,
+    expires_at: impl Fn(&V) -> Instant,
+    now: Instant,
+) {
+    if map.len() < MAX_CACHE_ENTRIES {
+        return;
+    }
+    map.retain(|_, v| now < expires_at(v));
+
+    let target = MAX_CACHE_ENTRIES - MAX_CACHE_ENTRIES / 10;
+    if map.len() <= target {
+        return;
+    }
+    let mut by_expiry: Vec<(Instant, K)> = map
+        .iter()
+        .map(|(k, v)| (expires_at(v), k.clone()))
+        .collect();
+    by_expiry.sort_unstable_by_key(|(expires_at, _)| *expires_at);
+    let excess = map.len() - target;
+    for (_, key) in by_expiry.into_iter().take(excess) {
+        map.remove(&key);
+    }
+    debug!("Cache full, dropped {excess} entries closest to expiring");
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[dynamic]
+pub static CACHE: Cache = Cache::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::FlagRecordType;
+
+    fn question(i: usize) -> Question {
+        let name = LabelSeq::from_text(&format!("n{i}.example."), &LabelSeq::new()).unwrap();
+        Question::new(name, FlagRecordType::A)
+    }
+
+    fn answer(ttl: u32) -> CachedAnswer {
+        let record: ResourceRecord = format!("example. {ttl} IN A 192.0.2.1").parse().unwrap();
+        CachedAnswer::Positive(vec![RRset::new(record)])
+    }
+
+    #[test]
+    fn answers_stay_within_the_limit() {
+        let cache = Cache::new();
+        cache.insert(question(0), answer(60), false);
+        for i in 1..=MAX_CACHE_ENTRIES {
+            cache.insert(question(i), answer(3600), false);
+        }
+        let len = cache.answers.lock().unwrap().len();
+        assert!(len <= MAX_CACHE_ENTRIES);
+        // The entry closest to expiring went first, the last one inserted stays
+        assert!(cache.get(&question(0)).is_none());
+        assert!(cache.get(&question(MAX_CACHE_ENTRIES)).is_some());
+    }
+
+    #[test]
+    fn delegations_stay_within_the_limit() {
+        let cache = Cache::new();
+        let server = DNSServer {
+            name: LabelSeq::from_text("ns.example.", &LabelSeq::new()).unwrap(),
+            ipv4addr: Some("192.0.2.53".parse().unwrap()),
+            ipv6addr: None,
+            port: 53,
+        };
+        for i in 0..=MAX_CACHE_ENTRIES {
+            cache.insert_delegation(question(i).name, vec![server.clone()], 3600);
+        }
+        assert!(cache.delegations.lock().unwrap().len() <= MAX_CACHE_ENTRIES);
+    }
+
+    #[test]
+    fn replacing_an_entry_drops_nothing() {
+        let cache = Cache::new();
+        for i in 0..MAX_CACHE_ENTRIES {
+            cache.insert(question(i), answer(3600), false);
+        }
+        cache.insert(question(0), answer(3600), true);
+        assert_eq!(cache.answers.lock().unwrap().len(), MAX_CACHE_ENTRIES);
+    }
+}
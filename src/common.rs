@@ -1,10 +1,17 @@
 use bitflags::bitflags;
 use static_init::dynamic;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 use std::string::String;
 
-#[derive(Debug)]
+use crate::error::Error;
+use crate::text;
+
+#[derive(Debug, PartialEq)]
 pub struct FlagQR(u16);
 
 bitflags! {
@@ -15,7 +22,7 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct FlagOpcode(u16);
 
 bitflags! {
@@ -24,6 +31,8 @@ bitflags! {
         const QUERY = 0b0_0000_00000000000;
         const IQUERY = 0b0_0001_00000000000;
         const STATUS = 0b0_0010_00000000000;
+        const NOTIFY = 0b0_0100_00000000000;
+        const UPDATE = 0b0_0101_00000000000;
     }
 }
 
@@ -38,7 +47,7 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct FlagTC(u16);
 
 bitflags! {
@@ -71,7 +80,32 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+/// Authentic Data, set by a validating resolver on answers it authenticated,
+/// as per RFC 4035 section 3.2.3
+#[derive(Debug, PartialEq)]
+pub struct FlagAD(u16);
+
+bitflags! {
+    impl FlagAD: u16 {
+        const RESET = 0b1111111111_0_11111;
+        const FALSE = 0b0000000000_0_00000;
+        const TRUE = 0b0000000000_1_00000;
+    }
+}
+
+/// Checking Disabled, as per RFC 4035 section 3.2.2
+#[derive(Debug, PartialEq)]
+pub struct FlagCD(u16);
+
+bitflags! {
+    impl FlagCD: u16 {
+        const RESET = 0b11111111111_0_1111;
+        const FALSE = 0b00000000000_0_0000;
+        const TRUE = 0b00000000000_1_0000;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FlagRCode(u16);
 
 bitflags! {
@@ -81,6 +115,13 @@ bitflags! {
         const FORMERR = 0b000000000000_0001;
         const SERVFAIL = 0b000000000000_0010;
         const NXDOMAIN = 0b000000000000_0011;
+        const NOTIMP = 0b000000000000_0100;
+        const REFUSED = 0b000000000000_0101;
+        const YXDOMAIN = 0b000000000000_0110;
+        const YXRRSET = 0b000000000000_0111;
+        const NXRRSET = 0b000000000000_1000;
+        const NOTAUTH = 0b000000000000_1001;
+        const NOTZONE = 0b000000000000_1010;
     }
 }
 
@@ -93,11 +134,29 @@ bitflags! {
         const NS = 2;
         const CNAME = 5;
         const SOA = 6;
+        const PTR = 12;
+        const MX = 15;
+        const TXT = 16;
         const AAAA = 28;
+        const SRV = 33;
+        /// Only known by the validator, its data is kept in generic form
+        const DNAME = 39;
+        const OPT = 41;
+        const DS = 43;
+        const RRSIG = 46;
+        const NSEC = 47;
+        const DNSKEY = 48;
+        const NSEC3 = 50;
+        const NSEC3PARAM = 51;
+        const TSIG = 250;
+        const IXFR = 251;
+        const AXFR = 252;
+        /// Only valid in questions, matches all types
+        const ANY = 255;
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FlagClassCode(u16);
 
 bitflags! {
@@ -106,6 +165,142 @@ bitflags! {
         const CS = 2;
         const CH = 3;
         const HS = 4;
+        /// Only valid in update requests, as per RFC 2136 section 2.4
+        const NONE = 254;
+        const ANY = 255;
+    }
+}
+
+/// Mnemonics of the record types known to this server
+const RECORD_TYPE_NAMES: &[(FlagRecordType, &str)] = &[
+    (FlagRecordType::A, "A"),
+    (FlagRecordType::NS, "NS"),
+    (FlagRecordType::CNAME, "CNAME"),
+    (FlagRecordType::SOA, "SOA"),
+    (FlagRecordType::PTR, "PTR"),
+    (FlagRecordType::MX, "MX"),
+    (FlagRecordType::TXT, "TXT"),
+    (FlagRecordType::AAAA, "AAAA"),
+    (FlagRecordType::SRV, "SRV"),
+    (FlagRecordType::DNAME, "DNAME"),
+    (FlagRecordType::OPT, "OPT"),
+    (FlagRecordType::DS, "DS"),
+    (FlagRecordType::RRSIG, "RRSIG"),
+    (FlagRecordType::NSEC, "NSEC"),
+    (FlagRecordType::DNSKEY, "DNSKEY"),
+    (FlagRecordType::NSEC3, "NSEC3"),
+    (FlagRecordType::NSEC3PARAM, "NSEC3PARAM"),
+    (FlagRecordType::TSIG, "TSIG"),
+    (FlagRecordType::IXFR, "IXFR"),
+    (FlagRecordType::AXFR, "AXFR"),
+    (FlagRecordType::ANY, "ANY"),
+];
+
+const CLASS_NAMES: &[(FlagClassCode, &str)] = &[
+    (FlagClassCode::IN, "IN"),
+    (FlagClassCode::CS, "CS"),
+    (FlagClassCode::CH, "CH"),
+    (FlagClassCode::HS, "HS"),
+    (FlagClassCode::NONE, "NONE"),
+    (FlagClassCode::ANY, "ANY"),
+];
+
+/// Write the mnemonic of `value`, or the generic form of RFC 3597 section 5 such as `TYPE65`
+fn fmt_mnemonic<T: PartialEq>(
+    names: &[(T, &str)],
+    value: &T,
+    bits: u16,
+    generic_prefix: &str,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    match names.iter().find(|(v, _)| v == value) {
+        Some((_, name)) => write!(f, "{name}"),
+        None => write!(f, "{generic_prefix}{bits}"),
+    }
+}
+
+impl fmt::Display for FlagRecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_mnemonic(RECORD_TYPE_NAMES, self, self.bits(), "TYPE", f)
+    }
+}
+
+impl FromStr for FlagRecordType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((rtype, _)) = RECORD_TYPE_NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(rtype.clone());
+        }
+        parse_generic(s, "TYPE")
+            .map(FlagRecordType::from_bits_retain)
+            .ok_or_else(|| Error::BadSyntax(format!("unknown record type {s:?}")))
+    }
+}
+
+impl fmt::Display for FlagClassCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_mnemonic(CLASS_NAMES, self, self.bits(), "CLASS", f)
+    }
+}
+
+impl FromStr for FlagClassCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((class, _)) = CLASS_NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(class.clone());
+        }
+        parse_generic(s, "CLASS")
+            .map(FlagClassCode::from_bits_retain)
+            .ok_or_else(|| Error::BadSyntax(format!("unknown class {s:?}")))
+    }
+}
+
+/// Read the generic form of a type or class, such as `TYPE65` or `CLASS3`
+fn parse_generic(s: &str, prefix: &str) -> Option<u16> {
+    let head = s.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    s[prefix.len()..].parse().ok()
+}
+
+impl fmt::Display for FlagOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bits() >> 11 {
+            0 => write!(f, "QUERY"),
+            1 => write!(f, "IQUERY"),
+            2 => write!(f, "STATUS"),
+            4 => write!(f, "NOTIFY"),
+            5 => write!(f, "UPDATE"),
+            opcode => write!(f, "OPCODE{opcode}"),
+        }
+    }
+}
+
+impl fmt::Display for FlagRCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bits() {
+            0 => write!(f, "NOERROR"),
+            1 => write!(f, "FORMERR"),
+            2 => write!(f, "SERVFAIL"),
+            3 => write!(f, "NXDOMAIN"),
+            4 => write!(f, "NOTIMP"),
+            5 => write!(f, "REFUSED"),
+            6 => write!(f, "YXDOMAIN"),
+            7 => write!(f, "YXRRSET"),
+            8 => write!(f, "NXRRSET"),
+            9 => write!(f, "NOTAUTH"),
+            10 => write!(f, "NOTZONE"),
+            rcode => write!(f, "RCODE{rcode}"),
+        }
     }
 }
 
@@ -127,12 +322,13 @@ impl ParseContext {
         self.current_idx
     }
 
+    /// Remaining bytes, empty if parsing went past the end
     pub fn current_slice(&self) -> &[u8] {
-        &self.root_buff[self.current_idx..]
+        self.slice_from(self.current_idx)
     }
 
     pub fn slice_from(&self, idx: usize) -> &[u8] {
-        &self.root_buff[idx..]
+        self.root_buff.get(idx..).unwrap_or(&[])
     }
 
     pub fn advance(&mut self, count: usize) -> &[u8] {
@@ -144,7 +340,8 @@ impl ParseContext {
 #[derive(Debug, Clone)]
 pub struct SerializeContext {
     root_buff: Vec<u8>,
-    label_locations: HashMap<String, usize>,
+    label_locations: HashMap<Vec<Vec<u8>>, usize>,
+    compress: bool,
 }
 
 impl SerializeContext {
@@ -152,18 +349,29 @@ impl SerializeContext {
         SerializeContext {
             root_buff: vec![],
             label_locations: HashMap::new(),
+            compress: true,
         }
     }
 
-    pub fn get_labels_key(labels: &[String]) -> String {
-        labels.join(".")
+    /// Context that never compresses names, as needed for the canonical form
+    /// of RFC 4034 section 6.2
+    pub fn uncompressed() -> SerializeContext {
+        SerializeContext {
+            compress: false,
+            ..SerializeContext::new()
+        }
     }
 
-    pub fn get_pointer(&self, key: &String) -> Option<&usize> {
+    /// Names are compared case-insensitively, so the key is lowercased
+    pub fn get_labels_key(labels: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        labels.iter().map(|l| l.to_ascii_lowercase()).collect()
+    }
+
+    pub fn get_pointer(&self, key: &Vec<Vec<u8>>) -> Option<&usize> {
         self.label_locations.get(key)
     }
 
-    pub fn set_pointer(&mut self, key: String, idx: usize) -> Option<usize> {
+    pub fn set_pointer(&mut self, key: Vec<Vec<u8>>, idx: usize) -> Option<usize> {
         self.label_locations.insert(key, idx)
     }
 
@@ -171,6 +379,19 @@ impl SerializeContext {
         self.root_buff.clone()
     }
 
+    pub fn len(&self) -> usize {
+        self.root_buff.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root_buff.is_empty()
+    }
+
+    /// Overwrite 2 bytes already written at `idx`
+    pub fn set_u16_at(&mut self, idx: usize, value: u16) {
+        self.root_buff[idx..idx + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
     pub fn append(&mut self, other: &mut Vec<u8>) {
         self.root_buff.append(other)
     }
@@ -184,12 +405,46 @@ impl SerializeContext {
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+/// Domain name as a sequence of labels, with the root label left out
+///
+/// Labels are arbitrary bytes. Comparison and hashing ignore ASCII case
+/// as per RFC 4343, while the original case is kept for output
+#[derive(Debug, Clone)]
 pub struct LabelSeq {
-    pub labels: Vec<String>,
+    pub labels: Vec<Vec<u8>>,
+}
+
+impl PartialEq for LabelSeq {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for LabelSeq {}
+
+impl Hash for LabelSeq {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels.len());
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
 }
 
 pub const MAX_LABEL_LEN: usize = 63;
+/// Maximum length of a name on the wire, as per RFC 1035 section 2.3.4
+pub const MAX_NAME_LEN: usize = 255;
+const MAX_POINTER_OFFSET: usize = 0x3fff;
+/// A name has at most 127 labels, so more pointers than that means a loop
+const MAX_POINTER_HOPS: usize = 127;
 
 impl LabelSeq {
     pub fn new() -> LabelSeq {
@@ -197,6 +452,9 @@ impl LabelSeq {
     }
 
     pub fn serialize(&self, context: &mut SerializeContext) {
+        if !context.compress {
+            return self.serialize_uncompressed(context);
+        }
         for i in 0..self.labels.len() {
             let s = &self.labels[i..];
             let key = SerializeContext::get_labels_key(s);
@@ -206,8 +464,11 @@ impl LabelSeq {
                 context.root_buff.extend_from_slice(&val);
                 return;
             } else {
-                context.set_pointer(key, context.root_buff.len());
-                let bytes = self.labels[i].as_bytes();
+                // Pointers have 14 bits, names further in the message cannot be pointed to
+                if context.root_buff.len() <= MAX_POINTER_OFFSET {
+                    context.set_pointer(key, context.root_buff.len());
+                }
+                let bytes = &self.labels[i];
                 let len = bytes.len() as u8;
                 context.root_buff.push(len);
                 context.root_buff.extend_from_slice(bytes);
@@ -216,80 +477,234 @@ impl LabelSeq {
         context.root_buff.push(0);
     }
 
-    pub fn from_string(s: &str) -> LabelSeq {
-        let mut labels = vec![];
+    /// Serialize without compression, for names in rdata of types defined after RFC 1035
+    pub fn serialize_uncompressed(&self, context: &mut SerializeContext) {
+        for label in &self.labels {
+            context.root_buff.push(label.len() as u8);
+            context.root_buff.extend_from_slice(label);
+        }
+        context.root_buff.push(0);
+    }
 
-        for part in s.split('.') {
-            labels.push(part.to_string());
+    /// Same name in lowercase, as in the canonical form of RFC 4034 section 6.2
+    pub fn to_lowercase(&self) -> LabelSeq {
+        LabelSeq {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
         }
+    }
 
-        LabelSeq { labels }
+    /// Order of names in the canonical form of RFC 4034 section 6.1, which compares
+    /// lowercased labels starting from the rightmost one
+    pub fn canonical_cmp(&self, other: &LabelSeq) -> Ordering {
+        let lowercase = |label: &Vec<u8>| label.to_ascii_lowercase();
+        self.labels
+            .iter()
+            .rev()
+            .map(lowercase)
+            .cmp(other.labels.iter().rev().map(lowercase))
     }
 
-    pub fn parse(context: &mut ParseContext) -> Result<LabelSeq, *const str> {
-        let buff = context.current_slice();
-        let (labels, parsed_bytes_count) = Self::parse_from_slice(buff, context);
-        context.advance(parsed_bytes_count);
-        Ok(LabelSeq {
-            labels: labels.unwrap(),
+    /// Parent of this name, or `None` for the root
+    pub fn parent(&self) -> Option<LabelSeq> {
+        let (_, labels) = self.labels.split_first()?;
+        Some(LabelSeq {
+            labels: labels.to_vec(),
         })
     }
 
-    fn parse_from_slice(
-        buff: &[u8],
-        context: &ParseContext,
-    ) -> (Result<Vec<String>, *const str>, usize) {
+    /// Whether this name is equal to or below `other`
+    pub fn is_subdomain_of(&self, other: &LabelSeq) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self
+                .labels
+                .iter()
+                .rev()
+                .zip(other.labels.iter().rev())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Split a plain dotted name, without escapes, into labels.
+    /// The trailing dot is optional
+    pub fn from_string(s: &str) -> LabelSeq {
         let mut labels = vec![];
-        let mut i = 0;
-        let last_buff_idx = buff.len() - 1;
-        let current_idx = context.current_idx();
 
-        loop {
-            // Example:
-            // label_len = 4
-            // | i |   |   |   |label_last_idx|
+        for part in s.split('.').filter(|part| !part.is_empty()) {
+            labels.push(part.as_bytes().to_vec());
+        }
 
-            if i > last_buff_idx {
-                return (Err("cannot read label length"), i);
-            }
+        LabelSeq { labels }
+    }
 
-            let label_len = buff[i] as usize;
-            if label_len == 0 {
-                return (Ok(labels), i + 1);
-            }
+    /// Read a name in presentation format, as per RFC 1035 section 5.1
+    ///
+    /// `@` stands for `origin`, and a name without trailing dot is relative to `origin`.
+    /// Dots and other special characters in labels are escaped as `\X` or `\DDD`
+    pub fn from_text(s: &str, origin: &LabelSeq) -> Result<LabelSeq, Error> {
+        if s == "@" {
+            return Ok(origin.clone());
+        }
+        if s == "." {
+            return Ok(LabelSeq::new());
+        }
 
-            if label_len >> 6 == 0b11 {
-                if i + 1 > last_buff_idx {
-                    return (Err("cannot parse label pointer"), i + 1);
+        let mut labels = vec![];
+        let mut label = vec![];
+        let mut absolute = false;
+        let bytes = s.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            absolute = false;
+            match bytes[i] {
+                b'.' => {
+                    if label.is_empty() {
+                        return Err(Error::BadSyntax(format!("empty label in {s:?}")));
+                    }
+                    labels.push(std::mem::take(&mut label));
+                    absolute = true;
+                    i += 1;
                 }
-
-                let pointer = (u16::from_be_bytes([buff[i], buff[i + 1]]) << 2 >> 2) as usize;
-                if pointer >= current_idx {
-                    return (Err("cannot reference to unparsed sequences"), i + 2);
+                b'\\' => {
+                    // Unescape here rather than upfront, so that escaped dots stay in the label
+                    let len = match bytes.get(i + 1) {
+                        Some(d) if d.is_ascii_digit() => 4,
+                        _ => 2,
+                    };
+                    let escape = &bytes[i..bytes.len().min(i + len)];
+                    label.extend(text::unescape_bytes(escape)?);
+                    i += len;
+                }
+                byte => {
+                    label.push(byte);
+                    i += 1;
                 }
+            }
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
 
-                let pointer_buff = context.slice_from(pointer);
-                let (rest_labels, _) = Self::parse_from_slice(pointer_buff, context);
-                let mut rest_labels = rest_labels.unwrap();
+        if !absolute {
+            labels.extend(origin.labels.iter().cloned());
+        }
 
-                labels.append(&mut rest_labels);
-                return (Ok(labels), i + 2);
-            }
+        if labels.iter().any(|label| label.len() > MAX_LABEL_LEN) {
+            return Err(Error::BadSyntax(format!("label too long in {s:?}")));
+        }
+        let name = LabelSeq { labels };
+        if name.wire_len() > MAX_NAME_LEN {
+            return Err(Error::BadSyntax(format!("name too long: {s:?}")));
+        }
+        Ok(name)
+    }
 
-            if label_len > MAX_LABEL_LEN {
-                return (Err("label is too long"), i + 1);
-            }
+    /// Length of the uncompressed name on the wire
+    pub fn wire_len(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
 
-            let label_last_idx = i + label_len;
-            if label_last_idx > last_buff_idx {
-                return (Err("cannot read label"), i + 1);
+    /// Parse a possibly compressed name
+    ///
+    /// Each compression pointer must point before the previous one, or before the name
+    /// for the first pointer, so that following them always terminates
+    pub fn parse(context: &mut ParseContext) -> Result<LabelSeq, Error> {
+        let buff = context.slice_from(0);
+        let mut labels = vec![];
+        let mut idx = context.current_idx();
+        let mut pointer_limit = context.current_idx();
+        let mut name_end_idx = None;
+        let mut hops = 0;
+        // Length of the name on the wire, including the root label
+        let mut name_len = 1;
+
+        loop {
+            // Example:
+            // label_len = 4
+            // | idx |   |   |   |   |
+
+            let Some(&label_len) = buff.get(idx) else {
+                return Err(Error::Truncated("label length"));
+            };
+            let label_len = label_len as usize;
+
+            match label_len >> 6 {
+                0b00 if label_len == 0 => break,
+                0b00 => {
+                    name_len += label_len + 1;
+                    if name_len > MAX_NAME_LEN {
+                        return Err(Error::BadLabel("name is too long"));
+                    }
+
+                    let Some(slice) = buff.get(idx + 1..idx + 1 + label_len) else {
+                        return Err(Error::Truncated("label"));
+                    };
+                    labels.push(slice.to_vec());
+
+                    idx += 1 + label_len;
+                }
+                0b11 => {
+                    let Some(&next) = buff.get(idx + 1) else {
+                        return Err(Error::Truncated("label pointer"));
+                    };
+                    let pointer = (label_len & 0b111111) << 8 | next as usize;
+                    if pointer >= pointer_limit {
+                        return Err(Error::BadPointer(pointer));
+                    }
+
+                    hops += 1;
+                    if hops > MAX_POINTER_HOPS {
+                        return Err(Error::BadPointer(pointer));
+                    }
+
+                    // Parsing resumes right after the first pointer
+                    name_end_idx.get_or_insert(idx + 2);
+                    pointer_limit = pointer;
+                    idx = pointer;
+                }
+                _ => return Err(Error::BadLabel("unsupported label type")),
             }
-            let slice = Vec::from(&buff[i + 1..=label_last_idx]);
-            let label = String::from_utf8(slice).unwrap();
-            labels.push(label);
+        }
 
-            i = label_last_idx + 1;
+        let name_end_idx = name_end_idx.unwrap_or(idx + 1);
+        context.advance(name_end_idx - context.current_idx());
+        Ok(LabelSeq { labels })
+    }
+}
+
+impl fmt::Display for LabelSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.labels.is_empty() {
+            return write!(f, ".");
+        }
+        for label in &self.labels {
+            for byte in label {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", *byte as char)?
+                    }
+                    0x21..=0x7e => write!(f, "{}", *byte as char)?,
+                    _ => write!(f, "\\{byte:03}")?,
+                }
+            }
+            write!(f, ".")?;
         }
+        Ok(())
+    }
+}
+
+/// Names without trailing dot are taken as relative to the root
+impl FromStr for LabelSeq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LabelSeq::from_text(s, &LabelSeq::new())
     }
 }
 
@@ -352,3 +767,126 @@ pub static ROOT_SERVERS: Vec<DNSServer> = {
         ("m.root-servers.net", "202.12.27.33", "2001:dc3::35"),
     )
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_at(buff: &[u8], start: usize) -> Result<LabelSeq, Error> {
+        let mut context = ParseContext::new(buff.to_vec());
+        context.advance(start);
+        LabelSeq::parse(&mut context)
+    }
+
+    #[test]
+    fn parse_follows_backward_pointers() {
+        // "a." at offset 0, then "b" pointing to it
+        let buff = [1, b'a', 0, 1, b'b', 0xc0, 0x00];
+        let mut context = ParseContext::new(buff.to_vec());
+        context.advance(3);
+        let name = LabelSeq::parse(&mut context).unwrap();
+        assert_eq!(name, LabelSeq::from_string("b.a."));
+        // Parsing resumes after the pointer
+        assert_eq!(context.current_idx(), buff.len());
+    }
+
+    #[test]
+    fn parse_rejects_pointer_loop() {
+        // Two pointers pointing at each other
+        let buff = [0xc0, 0x02, 0xc0, 0x00];
+        assert!(matches!(parse_at(&buff, 2), Err(Error::BadPointer(2))));
+        // Pointer to itself
+        assert!(matches!(
+            parse_at(&[0xc0, 0x00], 0),
+            Err(Error::BadPointer(0))
+        ));
+    }
+
+    #[test]
+    fn parse_rejects_forward_pointer() {
+        let buff = [0xc0, 0x02, 1, b'a', 0];
+        assert!(matches!(parse_at(&buff, 0), Err(Error::BadPointer(2))));
+    }
+
+    #[test]
+    fn parse_rejects_long_name() {
+        let name = |label_lens: &[usize]| {
+            let mut buff = vec![];
+            for &len in label_lens {
+                buff.push(len as u8);
+                buff.extend(std::iter::repeat_n(b'a', len));
+            }
+            buff.push(0);
+            buff
+        };
+        let longest = name(&[63, 63, 63, 61]);
+        assert_eq!(longest.len(), MAX_NAME_LEN);
+        assert_eq!(parse_at(&longest, 0).unwrap().labels.len(), 4);
+
+        let too_long = name(&[63, 63, 63, 62]);
+        assert!(matches!(parse_at(&too_long, 0), Err(Error::BadLabel(_))));
+        // Also when the name is made long through pointers
+        let mut buff = name(&[63, 63, 63]);
+        buff.extend([62]);
+        buff.extend([b'a'; 62]);
+        buff.extend([0xc0, 0x00]);
+        assert!(matches!(parse_at(&buff, 193), Err(Error::BadLabel(_))));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_input() {
+        assert!(matches!(parse_at(&[], 0), Err(Error::Truncated(_))));
+        assert!(matches!(parse_at(&[3, b'a'], 0), Err(Error::Truncated(_))));
+        assert!(matches!(parse_at(&[1, b'a'], 0), Err(Error::Truncated(_))));
+        assert!(matches!(parse_at(&[0xc0], 0), Err(Error::Truncated(_))));
+        assert!(matches!(parse_at(&[0x40], 0), Err(Error::BadLabel(_))));
+    }
+
+    #[test]
+    fn parse_keeps_non_utf8_labels() {
+        let buff = [2, 0xff, 0xfe, 3, b'c', b'o', b'm', 0];
+        let name = parse_at(&buff, 0).unwrap();
+        assert_eq!(name.labels[0], [0xff, 0xfe]);
+        assert_eq!(name.to_string(), "\\255\\254.com.");
+
+        let mut context = SerializeContext::new();
+        name.serialize(&mut context);
+        assert_eq!(context.to_vec(), buff);
+    }
+
+    #[test]
+    fn names_compare_without_case() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |name: &LabelSeq| {
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+            hasher.finish()
+        };
+        let lower = LabelSeq::from_string("www.example.com.");
+        let mixed = LabelSeq::from_string("WWW.Example.COM");
+        assert_eq!(lower, mixed);
+        assert_eq!(hash(&lower), hash(&mixed));
+        // Case is kept as it was written
+        assert_eq!(mixed.to_string(), "WWW.Example.COM.");
+
+        assert_ne!(lower, LabelSeq::from_string("www.example.org."));
+        assert_ne!(lower, LabelSeq::from_string("example.com."));
+        // Only ASCII letters fold, other bytes are compared as they are
+        let upper = LabelSeq {
+            labels: vec![vec![0xc9]],
+        };
+        let lower = LabelSeq {
+            labels: vec![vec![0xe9]],
+        };
+        assert_ne!(upper, lower);
+    }
+
+    #[test]
+    fn subdomains_compare_without_case() {
+        let name = LabelSeq::from_string("www.Example.com.");
+        assert!(name.is_subdomain_of(&LabelSeq::from_string("EXAMPLE.com.")));
+        assert!(name.is_subdomain_of(&LabelSeq::new()));
+        assert!(!name.is_subdomain_of(&LabelSeq::from_string("ample.com.")));
+    }
+}
@@ -1,7 +1,16 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 use clap::{arg, value_parser, Parser};
 
+use crate::acl::IpNetwork;
+use crate::common::LabelSeq;
+use crate::edns::DEFAULT_UDP_PAYLOAD_SIZE;
+use crate::secondary::SecondaryZone;
+use crate::signer::ZoneKey;
+use crate::tsig::{ServerKey, TsigKey};
+use crate::validator::TrustAnchor;
+
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
@@ -18,6 +27,89 @@ pub struct Config {
     #[arg(long, value_name = "PORT", default_value = "53",
           value_parser = value_parser!(u32).range(1..65536))]
     pub forward_server_port: u32,
+
+    /// Network allowed to query, can be repeated. Allow all if not set
+    #[arg(long, value_name = "NETWORK")]
+    pub allow_query: Vec<IpNetwork>,
+
+    /// Network allowed to transfer zones, can be repeated. Deny all if not set
+    #[arg(long, value_name = "NETWORK")]
+    pub allow_transfer: Vec<IpNetwork>,
+
+    /// Network allowed to update zones, can be repeated. Deny all if not set
+    #[arg(long, value_name = "NETWORK")]
+    pub allow_update: Vec<IpNetwork>,
+
+    /// Seconds before closing an idle TCP connection
+    #[arg(long, value_name = "SECONDS", default_value = "10")]
+    pub tcp_idle_timeout: u64,
+
+    /// Largest UDP payload advertised to EDNS clients
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_UDP_PAYLOAD_SIZE)]
+    pub edns_udp_size: u16,
+
+    /// Master file of a zone to serve authoritatively, can be repeated.
+    /// Updates of the zone are kept in the same file with `.jnl` appended
+    #[arg(long, value_name = "PATH")]
+    pub zone_file: Vec<PathBuf>,
+
+    /// Zone to pull from a primary server, as ZONE=IP[:PORT], can be repeated
+    #[arg(long, value_name = "ZONE=PRIMARY")]
+    pub secondary: Vec<SecondaryZone>,
+
+    /// Secondary server to notify when a zone changes, as IP[:PORT], can be repeated
+    #[arg(long, value_name = "SERVER", value_parser = parse_server_addr)]
+    pub notify: Vec<SocketAddr>,
+
+    /// TSIG key as [ALGORITHM:]NAME:SECRET, with hmac-sha256 (default) or hmac-sha512
+    /// and the secret in base64, can be repeated
+    #[arg(long, value_name = "KEY")]
+    pub tsig_key: Vec<TsigKey>,
+
+    /// Require zone transfers, NOTIFY and UPDATE to be signed with a TSIG key
+    #[arg(long)]
+    pub require_tsig: bool,
+
+    /// TSIG key to sign messages sent to a server with, as IP=KEY_NAME, can be repeated
+    #[arg(long, value_name = "IP=KEY")]
+    pub server_key: Vec<ServerKey>,
+
+    /// Key to sign a zone served from a master file with, as ZONE=ROLE:PATH with ROLE ksk or zsk
+    /// and PATH a PEM file of a PKCS #8 RSA, ECDSA P-256/P-384 or Ed25519 key, can be repeated
+    #[arg(long, value_name = "ZONE=ROLE:PATH")]
+    pub zone_key: Vec<ZoneKey>,
+
+    /// Chain the names of signed zones with NSEC3 instead of NSEC records
+    #[arg(long)]
+    pub nsec3: bool,
+
+    /// File of DS or DNSKEY records of the root zone, or of another zone to trust, to validate
+    /// answers of the iterative resolver with DNSSEC,
+    /// validation is off if not set
+    #[arg(long, value_name = "PATH", value_parser = parse_trust_anchor)]
+    pub trust_anchor: Option<TrustAnchor>,
+
+    /// Server to start iterative resolution from instead of the root servers,
+    /// as IP[:PORT], can be repeated
+    #[arg(long, value_name = "SERVER", value_parser = parse_server_addr)]
+    pub root_server: Vec<SocketAddr>,
+}
+
+pub const DEFAULT_DNS_PORT: u16 = 53;
+
+/// Parse `IP` or `IP:PORT`, the port defaults to 53
+pub fn parse_server_addr(s: &str) -> Result<SocketAddr, String> {
+    match s.parse::<SocketAddr>() {
+        Ok(addr) => Ok(addr),
+        Err(_) => s
+            .parse()
+            .map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT))
+            .map_err(|_| format!("invalid server address {s}")),
+    }
+}
+
+fn parse_trust_anchor(s: &str) -> Result<TrustAnchor, String> {
+    TrustAnchor::from_file(Path::new(s)).map_err(|err| format!("{s}: {err}"))
 }
 
 impl Config {
@@ -27,6 +119,23 @@ impl Config {
     pub fn forward_server_address_str(&self) -> String {
         format!("{}:{}", self.forward_server_ip, self.forward_server_port)
     }
+
+    /// Keys to sign the zone at `origin` with, none if it is not signed
+    pub fn keys_for_zone(&self, origin: &LabelSeq) -> Vec<ZoneKey> {
+        self.zone_key
+            .iter()
+            .filter(|key| key.zone == *origin)
+            .cloned()
+            .collect()
+    }
+
+    /// TSIG key to sign messages sent to the server at `ip` with, if any
+    pub fn key_for_server(&self, ip: IpAddr) -> Option<&TsigKey> {
+        let server_key = self.server_key.iter().find(|s| s.ip == ip)?;
+        self.tsig_key
+            .iter()
+            .find(|key| key.name == server_key.key_name)
+    }
 }
 
 pub fn load_config() -> Config {
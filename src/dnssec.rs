@@ -0,0 +1,318 @@
+//! Primitives of DNSSEC shared by validation and signing, as per RFC 4034 and RFC 5155
+
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+
+use crate::common::{LabelSeq, SerializeContext};
+use crate::resourserecord::{DNSKEYRecord, RRSIGRecord};
+use crate::rrset::RRset;
+
+/// Algorithms of DNSKEY and RRSIG records, as numbered by IANA
+pub const RSASHA256: u8 = 8;
+pub const ECDSAP256SHA256: u8 = 13;
+pub const ECDSAP384SHA384: u8 = 14;
+pub const ED25519: u8 = 15;
+
+/// Digest types of DS records, as numbered by IANA
+pub const DIGEST_SHA1: u8 = 1;
+pub const DIGEST_SHA256: u8 = 2;
+pub const DIGEST_SHA384: u8 = 4;
+
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(
+        algorithm,
+        RSASHA256 | ECDSAP256SHA256 | ECDSAP384SHA384 | ED25519
+    )
+}
+
+pub fn is_supported_digest(digest_type: u8) -> bool {
+    matches!(digest_type, DIGEST_SHA1 | DIGEST_SHA256 | DIGEST_SHA384)
+}
+
+/// Number of labels of `name` as counted by RRSIG records,
+/// leaving out a leading wildcard as per RFC 4034 section 3.1.3
+pub fn rrsig_labels(name: &LabelSeq) -> u8 {
+    let wildcard = name.labels.first().is_some_and(|label| label == b"*");
+    (name.labels.len() - wildcard as usize) as u8
+}
+
+/// Data covered by the signature of an RRset, as per RFC 4034 section 3.1.8.1
+///
+/// The RRset is put in canonical form and order, and the owner of an RRset expanded
+/// from a wildcard is put back to the wildcard
+pub fn signed_data(rrsig: &RRSIGRecord, rrset: &RRset) -> Vec<u8> {
+    let mut context = SerializeContext::uncompressed();
+    let rrsig = RRSIGRecord {
+        signer_name: rrsig.signer_name.to_lowercase(),
+        ..rrsig.clone()
+    };
+    rrsig.serialize_without_signature(&mut context);
+
+    let mut rrset = rrset.to_canonical();
+    rrset.ttl = rrsig.original_ttl;
+    if rrsig.labels < rrsig_labels(&rrset.name) {
+        let skipped = rrset.name.labels.len() - rrsig.labels as usize;
+        rrset.name.labels.splice(..skipped, [b"*".to_vec()]);
+    }
+    rrset.serialize_uncompressed(&mut context);
+    context.to_vec()
+}
+
+/// Digest of a DNSKEY as held by DS records, as per RFC 4034 section 5.1.4
+///
+/// Return `None` for unsupported digest types
+pub fn ds_digest(owner: &LabelSeq, key: &DNSKEYRecord, digest_type: u8) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        DIGEST_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        DIGEST_SHA256 => &digest::SHA256,
+        DIGEST_SHA384 => &digest::SHA384,
+        _ => return None,
+    };
+
+    let mut context = SerializeContext::uncompressed();
+    owner.to_lowercase().serialize_uncompressed(&mut context);
+    key.serialize(&mut context);
+    Some(
+        digest::digest(algorithm, &context.to_vec())
+            .as_ref()
+            .to_vec(),
+    )
+}
+
+/// Check a signature of `data` made with the private part of `key`
+///
+/// Keys are in the wire format of RFC 3110 for RSA, RFC 6605 for ECDSA
+/// and RFC 8080 for Ed25519
+pub fn verify_signature(key: &DNSKEYRecord, data: &[u8], sig: &[u8]) -> bool {
+    let public_key = &key.public_key[..];
+    match key.algorithm {
+        RSASHA256 => {
+            let Some((e, n)) = split_rsa_key(public_key) else {
+                return false;
+            };
+            RsaPublicKeyComponents { n, e }
+                .verify(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    data,
+                    sig,
+                )
+                .is_ok()
+        }
+        ECDSAP256SHA256 | ECDSAP384SHA384 => {
+            let algorithm = if key.algorithm == ECDSAP256SHA256 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            // Keys are given without the prefix of uncompressed points
+            let point = [&[0x04], public_key].concat();
+            UnparsedPublicKey::new(algorithm, point)
+                .verify(data, sig)
+                .is_ok()
+        }
+        ED25519 => UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(data, sig)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/// Exponent and modulus of an RSA key, as per RFC 3110 section 2
+fn split_rsa_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, rest) = key.split_first()?;
+    let (len, rest) = match len {
+        0 => {
+            let (len, rest) = rest.split_at_checked(2)?;
+            (u16::from_be_bytes([len[0], len[1]]) as usize, rest)
+        }
+        len => (len as usize, rest),
+    };
+    let (e, n) = rest.split_at_checked(len)?;
+    (!n.is_empty()).then_some((e, n))
+}
+
+/// Hash of a name for NSEC3 records, as per RFC 5155 section 5
+pub fn nsec3_hash(name: &LabelSeq, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut context = SerializeContext::uncompressed();
+    name.to_lowercase().serialize_uncompressed(&mut context);
+
+    let mut hash = context.to_vec();
+    for _ in 0..=iterations {
+        let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        ctx.update(&hash);
+        ctx.update(salt);
+        hash = ctx.finish().as_ref().to_vec();
+    }
+    hash
+}
+
+/// Whether the NSEC record of `owner`, whose next name is `next`, covers `name`:
+/// the owner comes before it and the next name after, or the record is the last
+/// of the zone and points back to the apex
+pub fn nsec_covers(owner: &LabelSeq, next: &LabelSeq, name: &LabelSeq) -> bool {
+    owner.canonical_cmp(name).is_lt()
+        && (name.canonical_cmp(next).is_lt() || next.canonical_cmp(owner).is_le())
+}
+
+/// Whether the NSEC3 record whose owner stands for the hash `owner`, and whose next
+/// hashed owner is `next`, covers `hash`
+pub fn nsec3_covers(owner: &[u8], next: &[u8], hash: &[u8]) -> bool {
+    match owner < next {
+        true => owner < hash && hash < next,
+        // Last record of the chain, which wraps around to the first
+        false => owner < hash || hash < next,
+    }
+}
+
+/// Candidates for the closest encloser of `name` in the zone of apex `zone`, from the
+/// deepest ancestor of `name` up to the apex, each with its next closer name
+/// as per RFC 5155 section 1.3
+pub fn enclosers(name: &LabelSeq, zone: &LabelSeq) -> impl Iterator<Item = (LabelSeq, LabelSeq)> {
+    let depth = name.labels.len().saturating_sub(zone.labels.len());
+    let name = name.clone();
+    (1..=depth).map(move |i| {
+        let encloser = LabelSeq {
+            labels: name.labels[i..].to_vec(),
+        };
+        let next_closer = LabelSeq {
+            labels: name.labels[i - 1..].to_vec(),
+        };
+        (encloser, next_closer)
+    })
+}
+
+/// Wildcard name directly below `name`
+pub fn wildcard_of(name: &LabelSeq) -> LabelSeq {
+    let mut wildcard = name.clone();
+    wildcard.labels.insert(0, b"*".to_vec());
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resourserecord::{ResourceData, ResourceRecord};
+    use crate::text;
+
+    /// Key, DS and signed MX RRset of the first example of RFC 8080 section 6
+    const DNSKEY: &str =
+        "example.com. 3600 IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=";
+    const DS: &str = "example.com. 3600 IN DS 3613 15 2 \
+        3aa5ab37efce57f737fc1627013fee07bdf241bd10f3b1964ab55c78e79a304b";
+    const MX: &str = "example.com. 3600 IN MX 10 mail.example.com.";
+    const RRSIG: &str = "example.com. 3600 IN RRSIG MX 15 2 3600 1440021600 1438207200 3613 \
+        example.com. oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg==";
+
+    fn key() -> DNSKEYRecord {
+        match DNSKEY.parse::<ResourceRecord>().unwrap().data {
+            ResourceData::DNSKEY(key) => key,
+            data => panic!("not a DNSKEY: {data}"),
+        }
+    }
+
+    fn rrsig() -> RRSIGRecord {
+        match RRSIG.parse::<ResourceRecord>().unwrap().data {
+            ResourceData::RRSIG(rrsig) => rrsig,
+            data => panic!("not an RRSIG: {data}"),
+        }
+    }
+
+    fn rrset(records: &[&str]) -> RRset {
+        let records: Vec<ResourceRecord> = records.iter().map(|r| r.parse().unwrap()).collect();
+        RRset::from_records(&records).remove(0)
+    }
+
+    #[test]
+    fn key_tag_and_ds_of_rfc8080() {
+        let key = key();
+        assert_eq!(key.key_tag(), 3613);
+
+        let ResourceData::DS(ds) = DS.parse::<ResourceRecord>().unwrap().data else {
+            panic!("not a DS");
+        };
+        let owner = LabelSeq::from_text("example.com.", &LabelSeq::new()).unwrap();
+        assert_eq!(ds_digest(&owner, &key, ds.digest_type), Some(ds.digest));
+    }
+
+    #[test]
+    fn verify_signature_of_rfc8080() {
+        let rrsig = rrsig();
+        let data = signed_data(&rrsig, &rrset(&[MX]));
+        assert!(verify_signature(&key(), &data, &rrsig.signature));
+    }
+
+    #[test]
+    fn signed_data_is_canonical() {
+        // Case of names and duplicates do not change the data covered by the signature
+        let rrsig = rrsig();
+        let records = [
+            "EXAMPLE.com. 3600 IN MX 10 Mail.Example.COM.",
+            "example.com. 3600 IN MX 10 mail.example.com.",
+        ];
+        let data = signed_data(&rrsig, &rrset(&records));
+        assert_eq!(data, signed_data(&rrsig, &rrset(&[MX])));
+        assert!(verify_signature(&key(), &data, &rrsig.signature));
+    }
+
+    #[test]
+    fn tampered_data_does_not_verify() {
+        let rrsig = rrsig();
+        let data = signed_data(
+            &rrsig,
+            &rrset(&["example.com. 3600 IN MX 20 mail.example.com."]),
+        );
+        assert!(!verify_signature(&key(), &data, &rrsig.signature));
+
+        let mut signature = rrsig.signature.clone();
+        signature[0] ^= 1;
+        let data = signed_data(&rrsig, &rrset(&[MX]));
+        assert!(!verify_signature(&key(), &data, &signature));
+    }
+
+    #[test]
+    fn nsec3_hash_of_rfc5155() {
+        // Hashes of the example zone of RFC 5155 appendix A
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        let hashes = [
+            ("example.", "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"),
+            ("a.example.", "35mthgpgcu1qg68fab165klnsnk3dpvl"),
+        ];
+        for (name, hash) in hashes {
+            let name = LabelSeq::from_text(name, &LabelSeq::new()).unwrap();
+            assert_eq!(
+                nsec3_hash(&name, &salt, 12),
+                text::parse_base32hex(hash).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn covering_wraps_around() {
+        let name = |s: &str| LabelSeq::from_text(s, &LabelSeq::new()).unwrap();
+        let (a, m, z) = (name("a.example."), name("m.example."), name("z.example."));
+        assert!(nsec_covers(&a, &z, &m));
+        assert!(!nsec_covers(&a, &m, &z));
+        // Last record of the zone, pointing back to the apex
+        assert!(nsec_covers(&m, &name("example."), &z));
+
+        assert!(nsec3_covers(&[1], &[3], &[2]));
+        assert!(!nsec3_covers(&[1], &[3], &[3]));
+        assert!(nsec3_covers(&[3], &[1], &[4]));
+        assert!(nsec3_covers(&[3], &[1], &[0]));
+        assert!(!nsec3_covers(&[3], &[1], &[2]));
+    }
+
+    #[test]
+    fn enclosers_up_to_the_apex() {
+        let name = |s: &str| LabelSeq::from_text(s, &LabelSeq::new()).unwrap();
+        let found: Vec<_> = enclosers(&name("a.b.example."), &name("example.")).collect();
+        assert_eq!(
+            found,
+            [
+                (name("b.example."), name("a.b.example.")),
+                (name("example."), name("b.example."))
+            ]
+        );
+        assert_eq!(enclosers(&name("example."), &name("example.")).count(), 0);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::common::{FlagClassCode, FlagRecordType, LabelSeq, ParseContext, SerializeContext};
+use crate::error::Error;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+
+/// UDP payload size advertised by default, as recommended by DNS flag day 2020
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+pub const EDNS_VERSION: u8 = 0;
+/// Extended RCODE for unsupported EDNS version, as per RFC 6891 section 6.1.3
+pub const EXTENDED_RCODE_BADVERS: u8 = 1;
+
+const DO_BIT: u32 = 1 << 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.code.to_be_bytes());
+        context.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        context.extend_from_slice(&self.data);
+    }
+
+    /// Parse all options in the next `size` bytes
+    pub fn parse_all(context: &mut ParseContext, size: usize) -> Result<Vec<EdnsOption>, Error> {
+        let mut options = vec![];
+        let mut remaining = size;
+
+        while remaining > 0 {
+            let buff = context.current_slice();
+            if remaining < 4 || buff.len() < 4 {
+                return Err(Error::Truncated("EDNS option"));
+            }
+
+            let code = u16::from_be_bytes([buff[0], buff[1]]);
+            let len = u16::from_be_bytes([buff[2], buff[3]]) as usize;
+            if remaining < 4 + len || buff.len() < 4 + len {
+                return Err(Error::Truncated("EDNS option data"));
+            }
+
+            let data = buff[4..4 + len].to_vec();
+            options.push(EdnsOption { code, data });
+            context.advance(4 + len);
+            remaining -= 4 + len;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Content of the OPT pseudo-record, as per RFC 6891
+///
+/// On the wire, the class holds the UDP payload size and the TTL holds
+/// the extended RCODE, the version and the DO bit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new() -> Edns {
+        Edns {
+            udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: EDNS_VERSION,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+
+    pub fn from_record(record: &ResourceRecord) -> Option<Edns> {
+        let ResourceData::OPT(options) = &record.data else {
+            return None;
+        };
+
+        Some(Edns {
+            udp_payload_size: record.class_code.bits(),
+            extended_rcode: (record.ttl >> 24) as u8,
+            version: (record.ttl >> 16) as u8,
+            dnssec_ok: record.ttl & DO_BIT != 0,
+            options: options.clone(),
+        })
+    }
+
+    pub fn to_record(&self) -> ResourceRecord {
+        let mut ttl = (self.extended_rcode as u32) << 24 | (self.version as u32) << 16;
+        if self.dnssec_ok {
+            ttl |= DO_BIT;
+        }
+
+        ResourceRecord {
+            name: LabelSeq::new(),
+            record_type: FlagRecordType::OPT,
+            class_code: FlagClassCode::from_bits_retain(self.udp_payload_size),
+            ttl,
+            data: ResourceData::OPT(self.options.clone()),
+        }
+    }
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::io;
+
+use tokio::time::error::Elapsed;
+
+use crate::tsig;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Input ended before the named part could be read
+    Truncated(&'static str),
+    /// Compression pointer to an invalid location
+    BadPointer(usize),
+    BadLabel(&'static str),
+    /// Resource data does not match its record type
+    BadRData(&'static str),
+    UnknownType(u16),
+    UnknownClass(u16),
+    BadOpcode(u16),
+    BadRCode(u16),
+    Io(io::Error),
+    Timeout,
+    /// No name server could be selected to continue resolution
+    NoServer,
+    /// Name server answered with neither an answer nor a referral closer to the name
+    BadReferral,
+    TooManyReferrals,
+    /// Presentation format text that cannot be read
+    BadSyntax(String),
+    /// Zone file or zone content that cannot be served
+    BadZone(String),
+    /// Transaction signature rejected, with the TSIG error of RFC 8945 section 3
+    BadTsig(u16),
+    /// DNSSEC validation failed, the data cannot be trusted
+    Bogus(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated(what) => write!(f, "truncated input: cannot read {what}"),
+            Error::BadPointer(pointer) => write!(f, "invalid compression pointer {pointer}"),
+            Error::BadLabel(reason) => write!(f, "invalid label: {reason}"),
+            Error::BadRData(reason) => write!(f, "invalid resource data: {reason}"),
+            Error::UnknownType(t) => write!(f, "unknown record type {t}"),
+            Error::UnknownClass(c) => write!(f, "unknown class code {c}"),
+            Error::BadOpcode(op) => write!(f, "unsupported opcode {op}"),
+            Error::BadRCode(rcode) => write!(f, "unsupported rcode {rcode}"),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::NoServer => write!(f, "no name server available"),
+            Error::BadReferral => write!(f, "server gave neither answer nor referral"),
+            Error::TooManyReferrals => write!(f, "too many referrals"),
+            Error::BadSyntax(reason) => write!(f, "syntax error: {reason}"),
+            Error::BadZone(reason) => write!(f, "invalid zone: {reason}"),
+            Error::BadTsig(error) => write!(f, "bad signature: {}", tsig::error_name(*error)),
+            Error::Bogus(reason) => write!(f, "DNSSEC validation failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        Error::Timeout
+    }
+}
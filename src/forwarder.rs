@@ -1,133 +1,327 @@
 use std::collections::HashMap;
-use std::io;
 use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
 use log::{debug, info};
 use rand::seq::SliceRandom;
-use tokio::net::UdpSocket;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
 
-use crate::common::{DNSServer, FlagRecordType, LabelSeq, ROOT_SERVERS};
+use crate::cache::{CachedAnswer, CACHE};
+use crate::common::{DNSServer, FlagAD, FlagRCode, FlagRecordType, FlagTC, LabelSeq, ROOT_SERVERS};
 use crate::config::Config;
+use crate::edns::Edns;
+use crate::error::Error;
+use crate::header::Header;
 use crate::message::Message;
 use crate::question::Question;
-use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::resourserecord::ResourceData;
+use crate::rrset::RRset;
+use crate::tcp;
+use crate::tsig::{Signer, TsigKey};
+use crate::validator;
 
-pub async fn forward_recursive(question: Question, config: &Config) -> io::Result<Message> {
-    send_question_to(question, config.forward_server_address_str()).await
+pub(crate) const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// Larger than the advertised EDNS payload size, so that a full buffer means a cut datagram
+const UDP_BUFFER_SIZE: usize = 4096;
+
+pub async fn forward_recursive(question: Question, config: &Config) -> Result<Message, Error> {
+    send_question_to(question, config.forward_server_address_str(), false).await
 }
 
-async fn send_question_to(question: Question, server_addr: String) -> io::Result<Message> {
+/// Ask a server, with the DO bit set if `dnssec_ok` so that it adds DNSSEC records
+async fn send_question_to(
+    question: Question,
+    server_addr: String,
+    dnssec_ok: bool,
+) -> Result<Message, Error> {
     let mut msg = Message::new();
-    msg.add_question(question);
-    send_message_to(msg, server_addr).await
+    msg.add_question(question.clone());
+    let mut edns = Edns::new();
+    edns.dnssec_ok = dnssec_ok;
+    msg.set_edns(edns);
+    let reply = send_message_to(msg, server_addr.clone(), None).await?;
+
+    // Server not supporting EDNS may reject the request, as per RFC 6891 section 7
+    let rcode = reply.header.get_rcode().ok();
+    if reply.edns().is_none() && matches!(rcode, Some(FlagRCode::FORMERR | FlagRCode::NOTIMP)) {
+        info!("Server at {server_addr} does not support EDNS, retry without it");
+        let mut msg = Message::new();
+        msg.add_question(question);
+        return send_message_to(msg, server_addr, None).await;
+    }
+
+    Ok(reply)
 }
 
-async fn send_message_to(msg: Message, server_addr: String) -> io::Result<Message> {
-    let raw_data = &msg.serialize()[..];
+/// Send a request and wait for the reply, over TCP if it does not fit in a datagram
+///
+/// The request is signed with `key`, if any, and so must be the reply
+pub(crate) async fn send_message_to(
+    msg: Message,
+    server_addr: String,
+    key: Option<&TsigKey>,
+) -> Result<Message, Error> {
+    let mut signer = key.map(|key| Signer::new(key.clone()));
+    let raw_data = match &mut signer {
+        Some(signer) => signer.sign(msg.serialize()),
+        None => msg.serialize(),
+    };
+    let raw_data = &raw_data[..];
 
     info!("Forwarding to server at {server_addr}");
 
-    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.unwrap();
-    let byte_sent = socket.send_to(raw_data, server_addr).await.unwrap();
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let byte_sent = socket.send_to(raw_data, &server_addr).await?;
     info!("Sent {byte_sent} bytes");
 
-    let mut buff = vec![0; 1024];
-    let msg_size = socket.recv(&mut buff).await.unwrap();
+    let mut buff = vec![0; UDP_BUFFER_SIZE];
+    let msg_size = timeout(UPSTREAM_TIMEOUT, socket.recv(&mut buff)).await??;
     info!("Received {msg_size} bytes");
+    buff.truncate(msg_size);
+
+    // A datagram filling the whole buffer may have been cut by recv
+    let is_truncated = msg_size == UDP_BUFFER_SIZE
+        || Header::peek(&buff).is_some_and(|h| h.get_tc() == FlagTC::TRUE);
+    if is_truncated {
+        info!("Reply from {server_addr} is truncated, retry over TCP");
+        buff = send_message_over_tcp(raw_data, server_addr).await?;
+    }
 
-    let reply = Message::parse(buff).unwrap();
+    let reply = Message::parse(buff.clone())?;
     // info!("Get reply {:?}", reply);
+    if let Some(signer) = &signer {
+        signer.verifier().verify(&buff, &reply)?;
+    }
 
     Ok(reply)
 }
 
+async fn send_message_over_tcp(raw_data: &[u8], server_addr: String) -> Result<Vec<u8>, Error> {
+    let mut stream = timeout(UPSTREAM_TIMEOUT, TcpStream::connect(&server_addr)).await??;
+    tcp::write_frame(&mut stream, raw_data).await?;
+    info!("Sent {} bytes over TCP", raw_data.len());
+
+    let buff = timeout(UPSTREAM_TIMEOUT, tcp::read_frame(&mut stream))
+        .await??
+        .ok_or(Error::Truncated("TCP reply"))?;
+    info!("Received {} bytes over TCP", buff.len());
+
+    Ok(buff)
+}
+
+/// Position of the iterative resolver in the delegation chain
 pub struct ForwardContext {
-    pub cache: HashMap<Question, Vec<DNSServer>>,
+    pub zone: LabelSeq,
+    pub servers: Vec<DNSServer>,
 }
 
 impl ForwardContext {
-    // TODO need this?
-    pub fn new() -> ForwardContext {
-        let root_domain_question = Question::new(LabelSeq::new(), FlagRecordType::A);
-        let servers = ROOT_SERVERS.to_owned();
+    /// Start from the root servers, or the ones configured instead
+    pub fn new(config: &Config) -> ForwardContext {
+        let servers = if config.root_server.is_empty() {
+            ROOT_SERVERS.to_owned()
+        } else {
+            config
+                .root_server
+                .iter()
+                .map(|addr| DNSServer {
+                    name: LabelSeq::new(),
+                    ipv4addr: match addr.ip() {
+                        IpAddr::V4(ip) => Some(ip),
+                        IpAddr::V6(_) => None,
+                    },
+                    ipv6addr: match addr.ip() {
+                        IpAddr::V4(_) => None,
+                        IpAddr::V6(ip) => Some(ip),
+                    },
+                    port: addr.port() as usize,
+                })
+                .collect()
+        };
         ForwardContext {
-            cache: HashMap::from([(root_domain_question, servers)]),
+            zone: LabelSeq::new(),
+            servers,
+        }
+    }
+
+    /// Skip to the closest known zone cut of `name`, if any has been cached
+    pub fn start_from_cache(&mut self, name: &LabelSeq) {
+        if let Some((zone, servers)) = CACHE.closest_delegation(name) {
+            if zone.is_subdomain_of(&self.zone) {
+                debug!("Start from cached zone {:?}", zone);
+                self.zone = zone;
+                self.servers = servers;
+            }
         }
     }
 }
 
 const MAX_ITER_FORWARD: usize = 10;
 
+/// Resolve a question from the root down, using and filling the cache
+///
+/// With a trust anchor configured, answers are validated with DNSSEC:
+/// AD is set on secure answers and bogus ones are an error
 pub async fn forward_iterative(
     question: Question,
     config: &Config,
     context: &mut ForwardContext,
-) -> io::Result<Message> {
-    // TODO check cache before request
-    // TODO select another server in case of failure
-    let server_ref = ROOT_SERVERS
-        .choose(&mut rand::thread_rng())
-        .expect("cannot select random server");
-    debug!("Use root server {:?}", server_ref);
-    let mut server_addr = server_ref.to_addr_str();
+) -> Result<Message, Error> {
+    if let Some((answer, authenticated)) = CACHE.get(&question) {
+        debug!("Cache hit for {:?}", question);
+        let mut msg = answer.to_message(question);
+        if authenticated {
+            msg.header.set_ad(FlagAD::TRUE);
+        }
+        return Ok(msg);
+    }
+
+    let mut res = resolve(question.clone(), config, context).await?;
+    let authenticated = match &config.trust_anchor {
+        Some(anchor) => validator::validate(config, anchor, &question, &res).await?,
+        None => false,
+    };
+    res.header.set_ad(if authenticated {
+        FlagAD::TRUE
+    } else {
+        FlagAD::FALSE
+    });
+
+    let answer = if extract_answer(&res, &question).rrsets.is_empty() {
+        CachedAnswer::negative_from(&res)
+    } else {
+        Some(CachedAnswer::Positive(RRset::from_records(&res.resources)))
+    };
+    if let Some(answer) = answer {
+        CACHE.insert(question, answer, authenticated);
+    }
+    Ok(res)
+}
+
+/// Follow referrals from the closest known zone cut until an answer or a negative answer,
+/// without cache nor validation of the answer
+pub(crate) async fn resolve(
+    question: Question,
+    config: &Config,
+    context: &mut ForwardContext,
+) -> Result<Message, Error> {
+    // DS records are served by the parent side of a zone cut, as per RFC 4035 section 3.1.4.1
+    let is_ds = question.record_type == FlagRecordType::DS.bits();
+    let lookup_name = match question.name.parent() {
+        Some(parent) if is_ds => parent,
+        _ => question.name.clone(),
+    };
+    let dnssec_ok = config.trust_anchor.is_some();
+
+    context.start_from_cache(&lookup_name);
     let mut counter = 0;
 
     loop {
-        let res = send_question_to(question.clone(), server_addr)
-            .await
-            .expect("request to server failed");
-        let ans = extract_answer(&res, &question.name);
-        if ans.resources.len() > 0 {
-            return Ok(res);
-        }
-
-        // TODO handle case where upstream dns server don't provide glue records
-        let server_ref = ans
+        // TODO select another server in case of failure
+        let server_ref = context
             .servers
             .choose(&mut rand::thread_rng())
-            .expect("cannot select random server");
-        debug!("Select server {:?}", server_ref);
-        server_addr = server_ref.to_addr_str();
+            .ok_or(Error::NoServer)?
+            .clone();
+        debug!("Select server {:?} for zone {:?}", server_ref, context.zone);
+        let mut server_addr = server_ref.to_addr_str();
 
         if server_addr.is_empty() {
             debug!("No glue record found for server {:?}", server_ref);
+            // Addresses of servers need no validation, and validating them could loop
             let question = Question::new(server_ref.name.clone(), FlagRecordType::A);
-            let mut context = ForwardContext::new();
-            let ans = Box::pin(forward_iterative(question, config, &mut context)).await?;
-            let ip = extract_ip_v4(ans)[0];
+            let mut context = ForwardContext::new(config);
+            let ans = Box::pin(resolve(question, config, &mut context)).await?;
+            let ip = *extract_ip_v4(ans).first().ok_or(Error::NoServer)?;
             server_addr = format!("{ip}:53");
         }
 
+        let res = send_question_to(question.clone(), server_addr, dnssec_ok).await?;
+        let ans = extract_answer(&res, &question);
+        if !ans.rrsets.is_empty() {
+            return Ok(res);
+        }
+
+        if CachedAnswer::negative_from(&res).is_some() {
+            debug!("Negative answer for {:?}", question);
+            return Ok(res);
+        }
+
+        // Only follow referrals that get closer to the requested name
+        match ans.zone {
+            Some(zone)
+                if zone != context.zone
+                    && zone.is_subdomain_of(&context.zone)
+                    && lookup_name.is_subdomain_of(&zone) =>
+            {
+                debug!("Referred to zone {:?}", zone);
+                CACHE.insert_delegation(zone.clone(), ans.servers.clone(), ans.ttl);
+                context.zone = zone;
+                context.servers = ans.servers;
+            }
+            _ => {
+                return Err(Error::BadReferral);
+            }
+        }
+
         counter += 1;
         if counter > MAX_ITER_FORWARD {
-            // TODO
-            panic!("max iterative reached")
+            return Err(Error::TooManyReferrals);
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct Answer {
+    zone: Option<LabelSeq>,
+    ttl: u32,
     servers: Vec<DNSServer>,
-    resources: Vec<ResourceRecord>,
+    /// RRsets of the answer section owned by the requested name
+    rrsets: Vec<RRset>,
 }
 
-fn extract_answer(msg: &Message, requested_name: &LabelSeq) -> Answer {
+/// Answer to `question` in `msg`, or the zone cut it refers to
+///
+/// Only NS records of the authority section make a referral. Those of the answer
+/// section are the answer to an NS query, such as one at the apex of a zone
+fn extract_answer(msg: &Message, question: &Question) -> Answer {
     /**
     Note that DNS server may not provide glue records for all NS entries
     The NS entries are often randomly shuffled and the first ones usually have glue record
     Therefore, we should keep the order of NS record in the answer
     */
 
+    let mut zone = None;
+    let mut ttl = u32::MAX;
     let mut servers = vec![];
     let mut name_to_svrs = HashMap::new();
-    let mut resources = vec![];
+    let mut rrsets = vec![];
+    let is_authoritative = msg.header.get_aa().bits() != 0;
 
-    let records = msg.resources.iter().chain(msg.auth_resources.iter());
+    let answers = RRset::from_records(&msg.resources);
+    let n_answer = answers.len();
+    let authority = RRset::from_records(&msg.auth_resources);
 
-    for record in records {
-        match &record.data {
-            ResourceData::NS(server_name) => {
+    for (i, rrset) in answers.into_iter().chain(authority).enumerate() {
+        // DS and DNSSEC records of a referral share its owner, yet are not the answer
+        let is_answer = i < n_answer;
+        let is_ns = rrset.record_type == FlagRecordType::NS;
+        if is_answer {
+            let is_requested = rrset.name == question.name
+                && (!is_ns
+                    || question.record_type == FlagRecordType::NS.bits()
+                    || is_authoritative);
+            if is_requested {
+                rrsets.push(rrset);
+            } else {
+                debug!("Ignore RRset: {:?}", rrset)
+            }
+        } else if is_ns {
+            for data in &rrset.data {
+                let ResourceData::NS(server_name) = data else {
+                    continue;
+                };
                 let server = DNSServer {
                     name: server_name.clone(),
                     ipv4addr: None,
@@ -137,13 +331,10 @@ fn extract_answer(msg: &Message, requested_name: &LabelSeq) -> Answer {
                 servers.push(server);
                 name_to_svrs.insert(server_name.clone(), servers.len() - 1);
             }
-            _ => {
-                if record.name == *requested_name {
-                    resources.push(record.clone());
-                } else {
-                    debug!("Ignore resource: {:?}", record)
-                }
-            }
+            zone = Some(rrset.name.clone());
+            ttl = ttl.min(rrset.ttl);
+        } else {
+            debug!("Ignore RRset: {:?}", rrset)
         }
     }
 
@@ -158,7 +349,12 @@ fn extract_answer(msg: &Message, requested_name: &LabelSeq) -> Answer {
         }
     }
 
-    Answer { servers, resources }
+    Answer {
+        zone,
+        ttl,
+        servers,
+        rrsets,
+    }
 }
 
 fn extract_ip_v4(msg: Message) -> Vec<Ipv4Addr> {
@@ -174,3 +370,69 @@ fn extract_ip_v4(msg: Message) -> Vec<Ipv4Addr> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::FlagAA;
+    use crate::resourserecord::ResourceRecord;
+
+    fn name(s: &str) -> LabelSeq {
+        LabelSeq::from_text(s, &LabelSeq::new()).unwrap()
+    }
+
+    fn record(s: &str) -> ResourceRecord {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ns_at_apex_is_an_answer() {
+        let question = Question::new(name("example.com."), FlagRecordType::NS);
+        let mut msg = Message::new();
+        msg.add_question(question.clone());
+        msg.add_resource(record("example.com. 3600 IN NS a.iana-servers.net."));
+        msg.add_resource(record("example.com. 3600 IN NS b.iana-servers.net."));
+
+        let ans = extract_answer(&msg, &question);
+        assert_eq!(ans.rrsets.len(), 1);
+        assert_eq!(ans.rrsets[0].data.len(), 2);
+        assert!(ans.zone.is_none());
+        assert!(ans.servers.is_empty());
+
+        // Any type asked of an authoritative server
+        let question = Question::new(name("example.com."), FlagRecordType::ANY);
+        msg.header.set_aa(FlagAA::TRUE);
+        assert_eq!(extract_answer(&msg, &question).rrsets.len(), 1);
+    }
+
+    #[test]
+    fn ns_of_authority_is_a_referral() {
+        let question = Question::new(name("example.com."), FlagRecordType::NS);
+        let mut msg = Message::new();
+        msg.add_question(question.clone());
+        msg.add_auth_resource(record("example.com. 172800 IN NS a.iana-servers.net."));
+        msg.add_addi_resource(record("a.iana-servers.net. 172800 IN A 199.43.135.53"));
+
+        let ans = extract_answer(&msg, &question);
+        assert!(ans.rrsets.is_empty());
+        assert_eq!(ans.zone, Some(name("example.com.")));
+        assert_eq!(ans.ttl, 172800);
+        assert_eq!(
+            ans.servers[0].ipv4addr,
+            Some("199.43.135.53".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn ns_of_answer_is_no_referral() {
+        // NS of another name in the answer section of a non-authoritative reply
+        let question = Question::new(name("www.example.com."), FlagRecordType::A);
+        let mut msg = Message::new();
+        msg.add_question(question.clone());
+        msg.add_resource(record("example.com. 3600 IN NS a.iana-servers.net."));
+
+        let ans = extract_answer(&msg, &question);
+        assert!(ans.rrsets.is_empty());
+        assert!(ans.zone.is_none());
+    }
+}
@@ -1,6 +1,9 @@
+use std::fmt;
+
 use crate::common::*;
+use crate::error::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Header {
     pub id: u16,
     pub flags: u16,
@@ -39,6 +42,8 @@ impl Header {
         header.set_ra(FlagRA::TRUE);
         header.set_rd(FlagRD::TRUE);
         header.set_ra(FlagRA::TRUE);
+        header.set_ad(FlagAD::FALSE);
+        header.set_rcode(FlagRCode::NOERROR);
 
         header
     }
@@ -61,12 +66,14 @@ impl Header {
         self
     }
 
-    pub fn get_opcode(&self) -> Result<FlagOpcode, *const str> {
+    pub fn get_opcode(&self) -> Result<FlagOpcode, Error> {
         match self.flags << 1 >> 12 {
             0 => Ok(FlagOpcode::QUERY),
             1 => Ok(FlagOpcode::IQUERY),
             2 => Ok(FlagOpcode::STATUS),
-            _ => Err("Invalid opcode flag"),
+            4 => Ok(FlagOpcode::NOTIFY),
+            5 => Ok(FlagOpcode::UPDATE),
+            opcode => Err(Error::BadOpcode(opcode)),
         }
     }
 
@@ -122,18 +129,51 @@ impl Header {
         }
     }
 
+    pub fn set_ad(&mut self, ad: FlagAD) -> &mut Self {
+        self.flags = self.flags & FlagAD::RESET.bits() | ad.bits();
+        self
+    }
+
+    pub fn get_ad(&self) -> FlagAD {
+        if self.flags << 10 >> 15 == 1 {
+            FlagAD::TRUE
+        } else {
+            FlagAD::FALSE
+        }
+    }
+
+    pub fn set_cd(&mut self, cd: FlagCD) -> &mut Self {
+        self.flags = self.flags & FlagCD::RESET.bits() | cd.bits();
+        self
+    }
+
+    pub fn get_cd(&self) -> FlagCD {
+        if self.flags << 11 >> 15 == 1 {
+            FlagCD::TRUE
+        } else {
+            FlagCD::FALSE
+        }
+    }
+
     pub fn set_rcode(&mut self, rcode: FlagRCode) -> &mut Self {
-        self.flags = self.flags & FlagRA::RESET.bits() | rcode.bits();
+        self.flags = self.flags & FlagRCode::RESET.bits() | rcode.bits();
         self
     }
 
-    pub fn get_rcode(&self) -> Result<FlagRCode, *const str> {
+    pub fn get_rcode(&self) -> Result<FlagRCode, Error> {
         match self.flags << 12 >> 12 {
             0 => Ok(FlagRCode::NOERROR),
             1 => Ok(FlagRCode::FORMERR),
             2 => Ok(FlagRCode::SERVFAIL),
             3 => Ok(FlagRCode::NXDOMAIN),
-            _ => Err("unsupported rcode flag"),
+            4 => Ok(FlagRCode::NOTIMP),
+            5 => Ok(FlagRCode::REFUSED),
+            6 => Ok(FlagRCode::YXDOMAIN),
+            7 => Ok(FlagRCode::YXRRSET),
+            8 => Ok(FlagRCode::NXRRSET),
+            9 => Ok(FlagRCode::NOTAUTH),
+            10 => Ok(FlagRCode::NOTZONE),
+            rcode => Err(Error::BadRCode(rcode)),
         }
     }
 
@@ -155,7 +195,23 @@ impl Header {
         context.append(&mut val)
     }
 
-    pub fn parse(context: &mut ParseContext) -> Result<Header, *const str> {
+    /// Read the header without validating it, so that a malformed request can still be replied to
+    pub fn peek(buff: &[u8]) -> Option<Header> {
+        if buff.len() < Self::SIZE {
+            return None;
+        }
+
+        Some(Header {
+            id: u16::from_be_bytes([buff[0], buff[1]]),
+            flags: u16::from_be_bytes([buff[2], buff[3]]),
+            n_question: u16::from_be_bytes([buff[4], buff[5]]),
+            n_answer: u16::from_be_bytes([buff[6], buff[7]]),
+            n_auth_res: u16::from_be_bytes([buff[8], buff[9]]),
+            n_addi_res: u16::from_be_bytes([buff[10], buff[11]]),
+        })
+    }
+
+    pub fn parse(context: &mut ParseContext) -> Result<Header, Error> {
         let mut header = Header {
             id: 0,
             flags: 0,
@@ -168,7 +224,7 @@ impl Header {
         let buff = context.current_slice();
 
         if buff.len() < Self::SIZE {
-            return Err("header too short");
+            return Err(Error::Truncated("header"));
         }
 
         header.flags = u16::from_be_bytes([buff[2], buff[3]]);
@@ -184,9 +240,43 @@ impl Header {
         Ok(header)
     }
 
-    fn validate_flags(&self) -> Result<(), *const str> {
+    fn validate_flags(&self) -> Result<(), Error> {
         self.get_opcode()?;
         self.get_rcode()?;
         Ok(())
     }
 }
+
+/// Same layout as the header printed by dig
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opcode = FlagOpcode::from_bits_retain(self.flags & !FlagOpcode::RESET.bits());
+        let rcode = FlagRCode::from_bits_retain(self.flags & !FlagRCode::RESET.bits());
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {opcode}, status: {rcode}, id: {}",
+            self.id
+        )?;
+
+        write!(f, ";; flags:")?;
+        let flags = [
+            (self.get_qr() == FlagQR::R, "qr"),
+            (self.get_aa().bits() != 0, "aa"),
+            (self.get_tc() == FlagTC::TRUE, "tc"),
+            (self.get_rd().bits() != 0, "rd"),
+            (self.get_ra().bits() != 0, "ra"),
+            (self.get_ad() == FlagAD::TRUE, "ad"),
+            (self.get_cd() == FlagCD::TRUE, "cd"),
+        ];
+        for (set, name) in flags {
+            if set {
+                write!(f, " {name}")?;
+            }
+        }
+        write!(
+            f,
+            "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.n_question, self.n_answer, self.n_auth_res, self.n_addi_res
+        )
+    }
+}
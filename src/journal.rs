@@ -0,0 +1,387 @@
+//! On-disk history of the changes made to a zone by dynamic updates
+//!
+//! The journal sits next to the master file of the zone, with `.jnl` appended to its name.
+//! It holds records in presentation format, as sets of differences laid out as in IXFR
+//! (RFC 1995 section 4): the old SOA and the deleted records, then the new SOA and the added
+//! records, each set between a `; serial` and an `; end of serial` comment line.
+//! Replaying it over the master file gives back the zone as last updated
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+
+use crate::common::{FlagRecordType, LabelSeq, SerializeContext};
+use crate::error::Error;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::text;
+use crate::transfer;
+use crate::zone::Zone;
+
+/// First line of each set of differences, followed by the serials it goes from and to
+const SET_START: &str = "; serial ";
+/// Last line of each set of differences, so that one cut short by a crash can be told apart
+const SET_END: &str = "; end of serial ";
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    /// Journal of the zone loaded from the master file at `zone_path`
+    pub fn for_zone_file(zone_path: &Path) -> Journal {
+        let mut path = zone_path.as_os_str().to_owned();
+        path.push(".jnl");
+        Journal { path: path.into() }
+    }
+
+    /// Apply the changes recorded in the journal to the zone loaded from its master file
+    ///
+    /// Sets of differences that do not start from the serial reached so far are skipped,
+    /// such as those made before the master file was edited by hand.
+    /// Unless it holds a single set that applies, the journal is then rewritten as one set
+    /// going from the master file to the zone as last updated, so that it does not grow forever
+    pub fn replay(&self, zone: Zone) -> Result<Zone, Error> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(zone),
+            Err(err) => return Err(err.into()),
+        };
+        let (sets, complete) = self.read_differences(&content)?;
+
+        let master = zone.clone();
+        let mut zone = zone;
+        let (mut applied, mut skipped) = (0, 0);
+        for differences in &sets {
+            if serial(&differences[0]) != Some(zone.serial()) {
+                skipped += 1;
+                continue;
+            }
+            let records = transfer::apply_differences(&zone, differences)?;
+            zone = Zone::from_records(records)?;
+            applied += 1;
+        }
+
+        if skipped > 0 {
+            warn!(
+                "Skipped {skipped} changes of {} that do not follow serial {}",
+                self.path.display(),
+                zone.serial()
+            );
+        }
+        if applied > 0 {
+            info!(
+                "Replayed {applied} changes of zone {} from {}",
+                zone.origin,
+                self.path.display()
+            );
+        }
+        if applied > 1 || skipped > 0 || !complete {
+            self.rewrite(&master, &zone)?;
+        }
+        Ok(zone)
+    }
+
+    /// Sets of differences of the journal, and whether all of them could be read
+    ///
+    /// A last set that is cut short or damaged, as left by a crash while appending it,
+    /// is dropped. Damage anywhere else is an error
+    fn read_differences(&self, content: &str) -> Result<(Vec<Vec<ResourceRecord>>, bool), Error> {
+        // Text of each set, the number of its first line and whether its end was written
+        let mut chunks: Vec<(String, usize, bool)> = vec![];
+        for (i, line) in content.lines().enumerate() {
+            if line.starts_with(SET_START) || chunks.is_empty() {
+                chunks.push((String::new(), i + 1, false));
+            }
+            let chunk = chunks.last_mut().expect("a chunk was just pushed");
+            if line.starts_with(SET_END) {
+                chunk.2 = true;
+            }
+            chunk.0.push_str(line);
+            chunk.0.push('\n');
+        }
+
+        let mut sets = vec![];
+        let n_chunks = chunks.len();
+        for (i, (text, first_line, ended)) in chunks.into_iter().enumerate() {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let set = match ended {
+                true => self.parse_set(&text, first_line),
+                false => Err(Error::BadZone(format!(
+                    "{}:{first_line}: change is not complete",
+                    self.path.display()
+                ))),
+            };
+            match set {
+                Ok(set) => sets.push(set),
+                Err(err) if i + 1 == n_chunks => {
+                    warn!("Drop incomplete change at the end of journal: {err}");
+                    return Ok((sets, false));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((sets, true))
+    }
+
+    /// Records of one set of differences, which must start with an SOA and hold exactly two
+    fn parse_set(&self, text: &str, first_line: usize) -> Result<Vec<ResourceRecord>, Error> {
+        let bad = |line: usize, err: &dyn fmt::Display| {
+            Error::BadZone(format!(
+                "{}:{}: {err}",
+                self.path.display(),
+                first_line + line - 1
+            ))
+        };
+
+        let mut records = vec![];
+        for entry in text::entries(text).map_err(|err| bad(1, &err))? {
+            let record = ResourceRecord::from_tokens(&entry.tokens, &LabelSeq::new())
+                .map_err(|err| bad(entry.line, &err))?;
+            records.push(record);
+        }
+
+        let n_soa = records.iter().filter(|r| serial(r).is_some()).count();
+        if records.first().and_then(serial).is_none() || n_soa != 2 {
+            return Err(bad(1, &"change does not have exactly two SOA records"));
+        }
+        Ok(records)
+    }
+
+    /// Append one set of differences and wait for it to reach the disk
+    pub fn append(
+        &self,
+        old_soa: &ResourceRecord,
+        deleted: &[ResourceRecord],
+        new_soa: &ResourceRecord,
+        added: &[ResourceRecord],
+    ) -> Result<(), Error> {
+        let content = format_differences(old_soa, deleted, new_soa, added);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Replace the journal with a single set of differences from `master` to `zone`,
+    /// or remove it if they do not differ
+    ///
+    /// The new journal is written aside and renamed over the old one,
+    /// so that a crash leaves either of them
+    fn rewrite(&self, master: &Zone, zone: &Zone) -> Result<(), Error> {
+        if zone.serial() == master.serial() {
+            info!(
+                "Remove journal {}, which has no change left",
+                self.path.display()
+            );
+            return match fs::remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+
+        let records: Vec<ResourceRecord> = zone.records().cloned().collect();
+        let (deleted, added) = differences(master, &records);
+        let content = format_differences(master.soa(), &deleted, zone.soa(), &added);
+
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, &self.path)?;
+        info!(
+            "Compacted journal {} to one change from serial {} to {}",
+            self.path.display(),
+            master.serial(),
+            zone.serial()
+        );
+        Ok(())
+    }
+}
+
+/// Records of `old` that are not in `new`, and records of `new` that are not in `old`,
+/// SOA left out
+pub fn differences(
+    old: &Zone,
+    new: &[ResourceRecord],
+) -> (Vec<ResourceRecord>, Vec<ResourceRecord>) {
+    let key = |r: &ResourceRecord| {
+        let mut context = SerializeContext::new();
+        r.serialize(&mut context);
+        context.to_vec()
+    };
+    let old_keys: HashSet<Vec<u8>> = old.records().map(key).collect();
+    let new_keys: HashSet<Vec<u8>> = new.iter().map(key).collect();
+
+    let deleted = old
+        .records()
+        .filter(|r| r.record_type != FlagRecordType::SOA && !new_keys.contains(&key(r)))
+        .cloned()
+        .collect();
+    let added = new
+        .iter()
+        .filter(|r| r.record_type != FlagRecordType::SOA && !old_keys.contains(&key(r)))
+        .cloned()
+        .collect();
+    (deleted, added)
+}
+
+/// One set of differences in presentation format, between its start and end lines
+fn format_differences(
+    old_soa: &ResourceRecord,
+    deleted: &[ResourceRecord],
+    new_soa: &ResourceRecord,
+    added: &[ResourceRecord],
+) -> String {
+    let new_serial = serial(new_soa).unwrap_or_default();
+    let mut content = format!(
+        "{SET_START}{} to {new_serial}\n",
+        serial(old_soa).unwrap_or_default()
+    );
+    for record in [old_soa].into_iter().chain(deleted) {
+        content.push_str(&format!("{record}\n"));
+    }
+    for record in [new_soa].into_iter().chain(added) {
+        content.push_str(&format!("{record}\n"));
+    }
+    content.push_str(&format!("{SET_END}{new_serial}\n"));
+    content
+}
+
+fn serial(record: &ResourceRecord) -> Option<u32> {
+    match &record.data {
+        ResourceData::SOA(soa) => Some(soa.serial),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(s: &str) -> ResourceRecord {
+        s.parse().unwrap()
+    }
+
+    fn soa(serial: u32) -> ResourceRecord {
+        record(&format!(
+            "example. 3600 IN SOA ns.example. admin.example. {serial} 7200 3600 1209600 300"
+        ))
+    }
+
+    fn zone(serial: u32) -> Zone {
+        Zone::from_records(vec![soa(serial), record("www.example. 300 IN A 192.0.2.1")]).unwrap()
+    }
+
+    /// Journal in a directory of its own, removed along with it
+    struct TestJournal(Journal);
+
+    impl TestJournal {
+        fn new(name: &str) -> TestJournal {
+            let dir = std::env::temp_dir().join(format!("journal-{name}-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let journal = Journal::for_zone_file(&dir.join("example.zone"));
+            let _ = fs::remove_file(&journal.path);
+            TestJournal(journal)
+        }
+
+        fn content(&self) -> String {
+            fs::read_to_string(&self.0.path).unwrap_or_default()
+        }
+    }
+
+    impl Drop for TestJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.path.parent().unwrap());
+        }
+    }
+
+    fn append_changes(journal: &Journal) {
+        let a = record("www.example. 300 IN A 192.0.2.1");
+        let b = record("www.example. 300 IN A 192.0.2.2");
+        let c = record("mail.example. 300 IN A 192.0.2.3");
+        journal.append(&soa(1), &[a], &soa(2), &[b]).unwrap();
+        journal.append(&soa(2), &[], &soa(3), &[c]).unwrap();
+    }
+
+    fn addresses(zone: &Zone) -> Vec<String> {
+        let mut addresses: Vec<String> = zone
+            .records()
+            .filter(|r| r.record_type == FlagRecordType::A)
+            .map(|r| r.to_string())
+            .collect();
+        addresses.sort();
+        addresses
+    }
+
+    #[test]
+    fn replay_applies_and_compacts() {
+        let test = TestJournal::new("compact");
+        append_changes(&test.0);
+        assert_eq!(test.content().matches(SET_START).count(), 2);
+
+        let zone = test.0.replay(zone(1)).unwrap();
+        assert_eq!(zone.serial(), 3);
+        assert_eq!(
+            addresses(&zone),
+            [
+                "mail.example.\t300\tIN\tA\t192.0.2.3",
+                "www.example.\t300\tIN\tA\t192.0.2.2"
+            ]
+        );
+
+        // Rewritten as one change that gives the same zone
+        assert_eq!(test.content().matches(SET_START).count(), 1);
+        let replayed = test.0.replay(self::zone(1)).unwrap();
+        assert_eq!(replayed.serial(), 3);
+        assert_eq!(addresses(&replayed), addresses(&zone));
+    }
+
+    #[test]
+    fn replay_drops_damaged_tail() {
+        let test = TestJournal::new("tail");
+        append_changes(&test.0);
+        let content = test.content();
+
+        // Crash in the middle of a record, then right before the end line
+        let cut = content.rfind("mail.example.").unwrap() + 8;
+        for tail in [
+            &content[..cut],
+            content.trim_end().rsplit_once('\n').unwrap().0,
+        ] {
+            fs::write(&test.0.path, tail).unwrap();
+            let zone = test.0.replay(zone(1)).unwrap();
+            assert_eq!(zone.serial(), 2);
+            assert!(!test.content().contains("serial 2 to 3"));
+        }
+    }
+
+    #[test]
+    fn replay_rejects_damage_before_tail() {
+        let test = TestJournal::new("damage");
+        append_changes(&test.0);
+        let content = test.content().replacen("192.0.2.1", "192.0.2", 1);
+        fs::write(&test.0.path, content).unwrap();
+        assert!(matches!(test.0.replay(zone(1)), Err(Error::BadZone(_))));
+    }
+
+    #[test]
+    fn replay_removes_stale_journal() {
+        let test = TestJournal::new("stale");
+        append_changes(&test.0);
+        // Master file edited by hand past the changes of the journal
+        let zone = test.0.replay(zone(5)).unwrap();
+        assert_eq!(zone.serial(), 5);
+        assert!(!test.0.path.exists());
+    }
+}
@@ -4,31 +4,80 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use log::{debug, info};
-use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
+use log::{debug, error, info};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{mpsc, watch};
 
+use crate::common::{FlagAD, FlagOpcode, FlagQR, FlagRCode, FlagRecordType};
+use crate::edns::{Edns, EDNS_VERSION, EXTENDED_RCODE_BADVERS};
+use crate::error::Error;
 use crate::forwarder::ForwardContext;
+use crate::header::Header;
+use crate::journal::Journal;
 use config::Config;
 
 use crate::message::Message;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::signer::KeyRole;
+use crate::zone::{Zone, ZONES};
 
+pub mod acl;
+pub mod cache;
 pub mod common;
 pub mod config;
+pub mod dnssec;
+pub mod edns;
+pub mod error;
 pub mod forwarder;
 pub mod header;
+pub mod journal;
 pub mod message;
+pub mod notify;
 pub mod question;
 pub mod resourserecord;
+pub mod rrset;
+pub mod secondary;
+pub mod signer;
+pub mod tcp;
+pub mod text;
+pub mod transfer;
+pub mod tsig;
+pub mod update;
 mod utils;
+pub mod validator;
+pub mod zone;
 
 pub struct DinosaurustServer {
     cfg: Config,
-    stoptx: Option<mpsc::Sender<()>>,
+    stoptx: Option<watch::Sender<bool>>,
 }
 
 type ResponsePair = (Vec<u8>, SocketAddr);
 
+/// Largest UDP message a client accepts without EDNS, as per RFC 1035 section 2.3.4
+pub const MAX_UDP_SIZE: usize = 512;
+const UDP_RECV_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl Protocol {
+    /// Size of the largest reply to a request with the given EDNS options
+    pub fn max_message_size(&self, edns: Option<&Edns>, server_udp_size: u16) -> usize {
+        match (self, edns) {
+            (Protocol::Udp, Some(edns)) => {
+                let client_size = (edns.udp_payload_size as usize).max(MAX_UDP_SIZE);
+                client_size.min((server_udp_size as usize).max(MAX_UDP_SIZE))
+            }
+            (Protocol::Udp, None) => MAX_UDP_SIZE,
+            (Protocol::Tcp, _) => u16::MAX as usize,
+        }
+    }
+}
+
 impl DinosaurustServer {
     pub fn new() -> DinosaurustServer {
         DinosaurustServer {
@@ -38,18 +87,57 @@ impl DinosaurustServer {
     }
 
     pub async fn start(&mut self) -> io::Result<()> {
+        // Subscribe before loading zones, so that secondaries are notified of them too
+        let zone_changes = ZONES.subscribe();
+        for server_key in &self.cfg.server_key {
+            if self.cfg.key_for_server(server_key.ip).is_none() {
+                let msg = format!("TSIG key {} is not defined", server_key.key_name);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+
+        for path in &self.cfg.zone_file {
+            let zone = Zone::from_file(path).map_err(io::Error::other)?;
+            info!("Loaded zone {} from {}", zone.origin, path.display());
+            let journal = Journal::for_zone_file(path);
+            let zone = journal.replay(zone).map_err(io::Error::other)?;
+            update::register(zone.origin.clone(), journal);
+            let zone = signer::sign_configured(&self.cfg, zone).map_err(io::Error::other)?;
+            ZONES.insert(zone);
+        }
+        for key in &self.cfg.zone_key {
+            let Some(zone) = ZONES.get(&key.zone) else {
+                let msg = format!("No zone file for the key of zone {}", key.zone);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            };
+            if key.role == KeyRole::Ksk {
+                let ds = ResourceRecord::new(
+                    key.zone.clone(),
+                    zone.soa().ttl,
+                    ResourceData::DS(key.ds()),
+                );
+                info!(
+                    "Signed zone {}, DS to publish at the parent: {ds}",
+                    key.zone
+                );
+            }
+        }
+
         let addr = self.cfg.socket_address_str();
         info!("Started listening at {}", addr);
 
-        let sock = Arc::new(UdpSocket::bind(addr).await?);
+        let sock = Arc::new(UdpSocket::bind(&addr).await?);
+        let listener = TcpListener::bind(&addr).await?;
         let (tx, mut rx) = mpsc::channel::<ResponsePair>(1024);
 
         // Task to send response
         let sock_clone = sock.clone();
         tokio::spawn(async move {
             while let Some((buff, addr)) = rx.recv().await {
-                let len = sock_clone.send_to(&buff[..], addr).await.unwrap();
-                debug!("\nSent {len} bytes to {addr}:");
+                match sock_clone.send_to(&buff[..], addr).await {
+                    Ok(len) => debug!("\nSent {len} bytes to {addr}:"),
+                    Err(err) => error!("Cannot send response to {addr}: {err}"),
+                }
                 // for x in &buff {
                 //     debug!("{:08b} ", x)
                 // }
@@ -58,44 +146,172 @@ impl DinosaurustServer {
         });
 
         // Channel to propagate stop signal
-        let (stoptx, mut stoprx) = mpsc::channel(1);
+        let (stoptx, stoprx) = watch::channel(false);
         self.stoptx = Some(stoptx);
 
         // Task to accept UDP datagram
         let cfg = self.cfg.clone();
+        let mut udp_stoprx = stoprx.clone();
         tokio::spawn(async move {
             loop {
-                if let Ok(_) = stoprx.try_recv() {
-                    break;
-                }
-                let mut buff = vec![0; 1024];
-                let (_len, peer_addr) = sock.recv_from(&mut buff).await.unwrap();
+                let mut buff = vec![0; UDP_RECV_BUFFER_SIZE];
+                let (len, peer_addr) = tokio::select! {
+                    _ = udp_stoprx.changed() => break,
+                    res = sock.recv_from(&mut buff) => match res {
+                        Ok(res) => res,
+                        Err(err) => {
+                            error!("Cannot receive datagram: {err}");
+                            continue;
+                        }
+                    },
+                };
+                buff.truncate(len);
                 let tx_clone = tx.clone();
                 let cfg = cfg.clone();
-                tokio::spawn(async move { handle_request(cfg, buff, tx_clone, peer_addr).await });
+                tokio::spawn(async move {
+                    for res in handle_request(&cfg, buff, peer_addr, Protocol::Udp).await {
+                        if let Err(err) = tx_clone.send((res, peer_addr)).await {
+                            error!("Cannot queue response to {peer_addr}: {err}");
+                        }
+                    }
+                });
             }
         });
 
+        // Task to notify secondaries of zone changes
+        if !self.cfg.notify.is_empty() {
+            let cfg = self.cfg.clone();
+            tokio::spawn(notify::notify_changes(cfg, zone_changes, stoprx.clone()));
+        }
+
+        // Task to renew signatures of signed zones
+        if !self.cfg.zone_key.is_empty() {
+            let cfg = self.cfg.clone();
+            tokio::spawn(signer::maintain(cfg, stoprx.clone()));
+        }
+
+        // Tasks to keep secondary zones up to date
+        for secondary in &self.cfg.secondary {
+            let key = self.cfg.key_for_server(secondary.primary.ip()).cloned();
+            tokio::spawn(secondary::maintain(secondary.clone(), key, stoprx.clone()));
+        }
+
+        // Task to accept TCP connections
+        let cfg = self.cfg.clone();
+        tokio::spawn(tcp::serve(listener, cfg, stoprx));
+
         Ok(())
     }
 
     pub async fn stop(&mut self) {
         if let Some(tx) = &self.stoptx {
             info!("Stopping server");
-            tx.send(()).await.unwrap()
+            if let Err(err) = tx.send(true) {
+                error!("Cannot stop server: {err}");
+            }
         }
     }
 }
 
+/// Answer a raw request, return the serialized replies or none if it must be dropped
+///
+/// Only zone transfers have more than one reply.
+/// Replies to a request signed with TSIG are signed with the same key
 async fn handle_request(
-    cfg: Config,
+    cfg: &Config,
     buff: Vec<u8>,
-    tx: mpsc::Sender<ResponsePair>,
     addr: SocketAddr,
-) {
-    // TODO handle & response error
-    let request = Message::parse(buff).unwrap();
+    protocol: Protocol,
+) -> Vec<Vec<u8>> {
+    let Some(header) = Header::peek(&buff) else {
+        debug!("Drop request from {addr}: too short to reply to");
+        return vec![];
+    };
+    if header.get_qr() == FlagQR::R {
+        debug!("Drop response sent to us by {addr}");
+        return vec![];
+    }
 
+    let (replies, max_size, mut signer) = match Message::parse(buff.clone()) {
+        Ok(request) => {
+            let signer = match tsig::check_request(&cfg.tsig_key, &buff, &request) {
+                Ok(signer) => signer,
+                Err(reply) => return vec![reply],
+            };
+            let edns = request.edns();
+            let max_size = protocol.max_message_size(edns.as_ref(), cfg.edns_udp_size);
+            let mut replies = match &edns {
+                Some(edns) if edns.version != EDNS_VERSION => vec![Message::reply_to(&request)],
+                _ if cfg.require_tsig && signer.is_none() && is_zone_management(&request) => {
+                    debug!("Refuse unsigned zone management request from {addr}");
+                    let mut reply = Message::reply_to(&request);
+                    reply.header.set_rcode(FlagRCode::REFUSED);
+                    vec![reply]
+                }
+                _ if request.header.get_opcode().ok() == Some(FlagOpcode::NOTIFY) => {
+                    vec![notify::answer_notify(cfg, &request, addr)]
+                }
+                _ if request.header.get_opcode().ok() == Some(FlagOpcode::UPDATE) => {
+                    vec![update::answer_update(cfg, &request, addr).await]
+                }
+                _ if transfer::is_transfer(&request) => {
+                    transfer::answer_transfer(cfg, &request, addr, protocol)
+                }
+                _ => vec![answer_request(cfg, &request, addr).await],
+            };
+            if let Some(edns) = edns {
+                for reply in &mut replies {
+                    reply.set_edns(reply_edns(cfg, &edns));
+                }
+            }
+            (replies, max_size, signer)
+        }
+        Err(err) => {
+            error!("Cannot parse request from {addr}: {err}");
+            let rcode = match err {
+                Error::BadOpcode(_) => FlagRCode::NOTIMP,
+                _ => FlagRCode::FORMERR,
+            };
+            let max_size = protocol.max_message_size(None, cfg.edns_udp_size);
+            (vec![Message::error_reply(&header, rcode)], max_size, None)
+        }
+    };
+
+    replies
+        .into_iter()
+        .map(|mut reply| {
+            debug!("\nReply: {:?}", reply);
+            match &mut signer {
+                Some(signer) => {
+                    let buff = reply.serialize_within(max_size - signer.tsig_len());
+                    signer.sign(buff)
+                }
+                None => reply.serialize_within(max_size),
+            }
+        })
+        .collect()
+}
+
+/// Whether the request is a zone transfer, NOTIFY or UPDATE
+fn is_zone_management(request: &Message) -> bool {
+    let opcode = request.header.get_opcode().ok();
+    transfer::is_transfer(request)
+        || opcode == Some(FlagOpcode::NOTIFY)
+        || opcode == Some(FlagOpcode::UPDATE)
+}
+
+/// OPT pseudo-record to put in the reply to a request having `request_edns`
+fn reply_edns(cfg: &Config, request_edns: &Edns) -> Edns {
+    let mut edns = Edns::new();
+    edns.udp_payload_size = cfg.edns_udp_size;
+    edns.dnssec_ok = request_edns.dnssec_ok;
+    if request_edns.version != EDNS_VERSION {
+        edns.extended_rcode = EXTENDED_RCODE_BADVERS;
+    }
+    edns
+}
+
+async fn answer_request(cfg: &Config, request: &Message, addr: SocketAddr) -> Message {
     debug!("\nGet request: {:?}", request);
     // debug!("Flags: {}", request.header.flags);
     // debug!("QR {:?}", request.header.get_qr());
@@ -106,17 +322,59 @@ async fn handle_request(
     // debug!("RA {:?}", request.header.get_ra());
     // debug!("RC {:?}", request.header.get_rcode());
 
-    let mut context = ForwardContext::new();
-    let res = forwarder::forward_iterative(request.questions[0].clone(), &cfg, &mut context)
-        .await
-        .unwrap();
+    let mut reply = Message::reply_to(request);
 
-    let mut reply = Message::reply_to(&request);
-    reply.copy_resources(&res);
+    if !acl::is_allowed(&cfg.allow_query, &addr.ip()) {
+        debug!("Refuse request from {addr}");
+        reply.header.set_rcode(FlagRCode::REFUSED);
+        return reply;
+    }
+
+    if request.header.get_opcode().ok() != Some(FlagOpcode::QUERY) {
+        reply.header.set_rcode(FlagRCode::NOTIMP);
+        return reply;
+    }
+
+    let [question] = &request.questions[..] else {
+        error!("Request from {addr} must have exactly one question");
+        return Message::error_reply(&request.header, FlagRCode::FORMERR);
+    };
+
+    let dnssec_ok = request.edns().is_some_and(|edns| edns.dnssec_ok);
+    // DS records are served by the parent side of a zone cut, as per RFC 4035 section 3.1.4.1
+    let zone = match question.name.parent() {
+        Some(parent) if question.record_type == FlagRecordType::DS.bits() => {
+            ZONES.find(&parent).or_else(|| ZONES.find(&question.name))
+        }
+        _ => ZONES.find(&question.name),
+    };
+    if let Some(zone) = zone {
+        zone.answer(question, dnssec_ok, &mut reply);
+        return reply;
+    }
 
-    debug!("\nReply: {:?}", reply);
+    let mut context = ForwardContext::new(cfg);
+    let res = match forwarder::forward_iterative(question.clone(), cfg, &mut context).await {
+        Ok(res) => res,
+        Err(err) => {
+            error!("Cannot resolve {:?}: {err}", question);
+            reply.header.set_rcode(FlagRCode::SERVFAIL);
+            return reply;
+        }
+    };
 
-    let res = reply.serialize();
+    reply.copy_resources(&res);
+    if let Ok(rcode) = res.header.get_rcode() {
+        reply.header.set_rcode(rcode);
+    }
+    if !dnssec_ok {
+        reply.strip_dnssec(&FlagRecordType::from_bits_retain(question.record_type));
+    }
+    // Only clients that asked for DNSSEC or authenticated data are told, as per RFC 6840 section 5.8
+    if res.header.get_ad() == FlagAD::TRUE && (dnssec_ok || request.header.get_ad() == FlagAD::TRUE)
+    {
+        reply.header.set_ad(FlagAD::TRUE);
+    }
 
-    tx.send((res, addr)).await.unwrap()
+    reply
 }
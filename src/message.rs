@@ -1,7 +1,17 @@
-use crate::common::{ParseContext, SerializeContext};
+use std::fmt;
+
+use crate::common::{FlagRCode, FlagRecordType, FlagTC, ParseContext, SerializeContext};
+use crate::edns::Edns;
+use crate::error::Error;
 use crate::header::Header;
 use crate::question::Question;
 use crate::resourserecord::ResourceRecord;
+use crate::rrset::RRset;
+
+/// Root name, type and class
+const MIN_QUESTION_SIZE: usize = 5;
+/// Root name, type, class, TTL and RDLENGTH
+const MIN_RECORD_SIZE: usize = 11;
 
 #[derive(Debug)]
 pub struct Message {
@@ -38,6 +48,20 @@ impl Message {
         }
     }
 
+    /// Header-only reply to a request that cannot be served
+    pub fn error_reply(request_header: &Header, rcode: FlagRCode) -> Message {
+        let mut header = Header::reply_to(request_header);
+        header.set_rcode(rcode);
+
+        Message {
+            header,
+            questions: vec![],
+            resources: vec![],
+            auth_resources: vec![],
+            addi_resources: vec![],
+        }
+    }
+
     pub fn copy_resources(&mut self, other: &Self) {
         self.header.n_answer = other.header.n_answer;
         self.header.n_auth_res = other.header.n_auth_res;
@@ -45,6 +69,27 @@ impl Message {
         self.auth_resources = other.auth_resources.clone();
     }
 
+    /// Drop the DNSSEC records a client did not ask for, when it did not set the DO bit,
+    /// as per RFC 4035 section 3.2.1
+    pub fn strip_dnssec(&mut self, qtype: &FlagRecordType) {
+        let is_dnssec = |r: &ResourceRecord| {
+            r.record_type != *qtype
+                && [
+                    FlagRecordType::RRSIG,
+                    FlagRecordType::NSEC,
+                    FlagRecordType::NSEC3,
+                ]
+                .contains(&r.record_type)
+        };
+        self.resources.retain(|r| !is_dnssec(r));
+        self.auth_resources
+            .retain(|r| !is_dnssec(r) && r.record_type != FlagRecordType::DS);
+        self.addi_resources.retain(|r| !is_dnssec(r));
+        self.header.n_answer = self.resources.len() as u16;
+        self.header.n_auth_res = self.auth_resources.len() as u16;
+        self.header.n_addi_res = self.addi_resources.len() as u16;
+    }
+
     pub fn add_question(&mut self, question: Question) -> &mut Self {
         self.questions.push(question);
         self.header.n_question += 1;
@@ -57,6 +102,26 @@ impl Message {
         self
     }
 
+    /// Add the records of an RRset to the answer section
+    pub fn add_rrset(&mut self, rrset: &RRset) -> &mut Self {
+        for record in rrset.to_records() {
+            self.add_resource(record);
+        }
+        self
+    }
+
+    pub fn add_auth_resource(&mut self, resource: ResourceRecord) -> &mut Self {
+        self.auth_resources.push(resource);
+        self.header.n_auth_res += 1;
+        self
+    }
+
+    pub fn add_addi_resource(&mut self, resource: ResourceRecord) -> &mut Self {
+        self.addi_resources.push(resource);
+        self.header.n_addi_res += 1;
+        self
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut context = SerializeContext::new();
         self.header.serialize(&mut context);
@@ -76,12 +141,124 @@ impl Message {
         context.to_vec()
     }
 
-    pub fn parse(buff: Vec<u8>) -> Result<Message, *const str> {
+    pub fn edns(&self) -> Option<Edns> {
+        self.addi_resources.iter().find_map(Edns::from_record)
+    }
+
+    /// Replace the OPT pseudo-record, if any, with the given one
+    pub fn set_edns(&mut self, edns: Edns) -> &mut Self {
+        self.addi_resources
+            .retain(|r| r.record_type != FlagRecordType::OPT);
+        self.addi_resources.push(edns.to_record());
+        self.header.n_addi_res = self.addi_resources.len() as u16;
+        self
+    }
+
+    /// Serialize the message, dropping whole RRsets from the end until it fits in `limit` bytes
+    ///
+    /// Additional records are dropped first, then authority and answer records.
+    /// The OPT pseudo-record is always kept.
+    /// TC is set if answer or authority records had to be dropped, as per RFC 2181 section 9
+    pub fn serialize_within(&mut self, limit: usize) -> Vec<u8> {
+        loop {
+            let buff = self.serialize();
+            if buff.len() <= limit {
+                return buff;
+            }
+
+            if self
+                .addi_resources
+                .iter()
+                .any(|r| r.record_type != FlagRecordType::OPT)
+            {
+                pop_rrset(&mut self.addi_resources);
+                self.header.n_addi_res = self.addi_resources.len() as u16;
+            } else if !self.auth_resources.is_empty() {
+                pop_rrset(&mut self.auth_resources);
+                self.header.n_auth_res = self.auth_resources.len() as u16;
+                self.header.set_tc(FlagTC::TRUE);
+            } else if !self.resources.is_empty() {
+                pop_rrset(&mut self.resources);
+                self.header.n_answer = self.resources.len() as u16;
+                self.header.set_tc(FlagTC::TRUE);
+            } else {
+                // Only header and questions left, nothing more can be dropped
+                self.header.set_tc(FlagTC::TRUE);
+                return self.serialize();
+            }
+        }
+    }
+
+    /// Spread answer records over as many messages as needed for each to fit in `limit` bytes,
+    /// as for zone transfers over TCP (RFC 5936 section 2.2)
+    ///
+    /// Only the first message has the question. Authority and additional records are dropped
+    pub fn split_answers(&self, limit: usize) -> Vec<Message> {
+        let start = |with_question: bool| {
+            let mut context = SerializeContext::new();
+            self.header.serialize(&mut context);
+            if with_question {
+                for q in &self.questions {
+                    q.serialize(&mut context);
+                }
+            }
+            context
+        };
+
+        let mut messages = vec![];
+        let mut first = 0;
+        let mut context = start(true);
+        for (i, r) in self.resources.iter().enumerate() {
+            r.serialize(&mut context);
+            // A record too large on its own still gets a message
+            if context.len() > limit && i > first {
+                messages.push(self.part(first..i, messages.is_empty()));
+                first = i;
+                context = start(false);
+                r.serialize(&mut context);
+            }
+        }
+        messages.push(self.part(first..self.resources.len(), messages.is_empty()));
+
+        messages
+    }
+
+    fn part(&self, range: std::ops::Range<usize>, with_question: bool) -> Message {
+        let mut header = self.header.clone();
+        let questions = if with_question {
+            self.questions.clone()
+        } else {
+            vec![]
+        };
+        header.n_question = questions.len() as u16;
+        header.n_answer = range.len() as u16;
+        header.n_auth_res = 0;
+        header.n_addi_res = 0;
+
+        Message {
+            header,
+            questions,
+            resources: self.resources[range].to_vec(),
+            auth_resources: vec![],
+            addi_resources: vec![],
+        }
+    }
+
+    pub fn parse(buff: Vec<u8>) -> Result<Message, Error> {
         let mut context = ParseContext::new(buff);
         let mut message = Self::new();
 
         message.header = Header::parse(&mut context)?;
 
+        // Reject counts that cannot fit in the message before parsing anything
+        let header = &message.header;
+        let n_record =
+            header.n_answer as usize + header.n_auth_res as usize + header.n_addi_res as usize;
+        let min_size = header.n_question as usize * MIN_QUESTION_SIZE + n_record * MIN_RECORD_SIZE;
+        if min_size > context.current_slice().len() {
+            return Err(Error::Truncated("records announced in header"));
+        }
+
         for _ in 0..message.header.n_question {
             let question = Question::parse(&mut context)?;
             message.questions.push(question);
@@ -105,3 +282,135 @@ impl Message {
         Ok(message)
     }
 }
+
+/// Same layout as the output of dig, with the OPT pseudo-record shown apart
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.header)?;
+
+        if let Some(edns) = self.edns() {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            write!(f, "; EDNS: version: {}, flags:", edns.version)?;
+            if edns.dnssec_ok {
+                write!(f, " do")?;
+            }
+            writeln!(f, "; udp: {}", edns.udp_payload_size)?;
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for q in &self.questions {
+                writeln!(f, ";{q}")?;
+            }
+        }
+
+        let additional: Vec<&ResourceRecord> = self
+            .addi_resources
+            .iter()
+            .filter(|r| r.record_type != FlagRecordType::OPT)
+            .collect();
+        let sections = [
+            ("ANSWER", self.resources.iter().collect::<Vec<_>>()),
+            ("AUTHORITY", self.auth_resources.iter().collect()),
+            ("ADDITIONAL", additional),
+        ];
+        for (name, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {name} SECTION:")?;
+            for r in records {
+                writeln!(f, "{r}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove the last record other than OPT, along with the records before it
+/// having the same owner, type and class
+fn pop_rrset(records: &mut Vec<ResourceRecord>) {
+    let Some(mut idx) = records
+        .iter()
+        .rposition(|r| r.record_type != FlagRecordType::OPT)
+    else {
+        return;
+    };
+
+    let last = records.remove(idx);
+    while idx > 0 {
+        let r = &records[idx - 1];
+        if r.name != last.name
+            || r.record_type != last.record_type
+            || r.class_code != last.class_code
+        {
+            break;
+        }
+        records.remove(idx - 1);
+        idx -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::LabelSeq;
+
+    /// Header with the given counts of questions, answers, authority and additional records
+    fn header(counts: [u16; 4]) -> Vec<u8> {
+        let mut buff = vec![0x12, 0x34, 0x01, 0x00];
+        for count in counts {
+            buff.extend(count.to_be_bytes());
+        }
+        buff
+    }
+
+    #[test]
+    fn parse_rejects_empty_buffer() {
+        assert!(matches!(Message::parse(vec![]), Err(Error::Truncated(_))));
+        assert!(matches!(
+            Message::parse(vec![0x12, 0x34, 0x01]),
+            Err(Error::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn parse_rejects_counts_larger_than_message() {
+        let mut buff = header([1, 0, 0, 0]);
+        assert!(matches!(
+            Message::parse(buff.clone()),
+            Err(Error::Truncated(_))
+        ));
+
+        buff.extend([0, 0, 1, 0, 1]);
+        assert_eq!(Message::parse(buff.clone()).unwrap().questions.len(), 1);
+
+        // Counts of records cannot be trusted to size anything either
+        let mut buff = header([0, u16::MAX, u16::MAX, u16::MAX]);
+        buff.extend([0; 11]);
+        assert!(matches!(Message::parse(buff), Err(Error::Truncated(_))));
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let mut msg = Message::new();
+        msg.add_question(Question::new(
+            LabelSeq::from_string("www.example.com."),
+            FlagRecordType::A,
+        ));
+        msg.add_resource("www.example.com. 300 IN A 192.0.2.1".parse().unwrap());
+        msg.add_auth_resource("example.com. 300 IN NS ns.example.com.".parse().unwrap());
+
+        let parsed = Message::parse(msg.serialize()).unwrap();
+        assert_eq!(parsed.questions[0], msg.questions[0]);
+        assert_eq!(
+            parsed.resources[0].to_string(),
+            msg.resources[0].to_string()
+        );
+        assert_eq!(
+            parsed.auth_resources[0].to_string(),
+            msg.auth_resources[0].to_string()
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Change notification of zones, as per RFC 1996
+
+use std::net::SocketAddr;
+
+use log::{debug, info, warn};
+use tokio::sync::{broadcast, watch};
+
+use crate::common::{FlagAA, FlagOpcode, FlagRCode, FlagRecordType, LabelSeq};
+use crate::config::Config;
+use crate::forwarder;
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::ResourceRecord;
+use crate::secondary;
+use crate::tsig::TsigKey;
+use crate::zone::ZONES;
+
+/// Attempts to notify a server before giving up, as suggested by RFC 1996 section 3.6
+const MAX_NOTIFY_ATTEMPTS: usize = 5;
+
+/// Notify the servers of `cfg.notify` of every change of zone, until the server stops
+pub async fn notify_changes(
+    cfg: Config,
+    mut changes: broadcast::Receiver<LabelSeq>,
+    mut stoprx: watch::Receiver<bool>,
+) {
+    loop {
+        let origin = tokio::select! {
+            _ = stoprx.changed() => break,
+            res = changes.recv() => match res {
+                Ok(origin) => origin,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Missed {n} zone changes to notify");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        let Some(zone) = ZONES.get(&origin) else {
+            continue;
+        };
+        for target in &cfg.notify {
+            let key = cfg.key_for_server(target.ip()).cloned();
+            tokio::spawn(send_notify(*target, zone.soa().clone(), key));
+        }
+    }
+}
+
+/// Send NOTIFY for the zone of `soa` until `target` answers
+async fn send_notify(target: SocketAddr, soa: ResourceRecord, key: Option<TsigKey>) {
+    for attempt in 1..=MAX_NOTIFY_ATTEMPTS {
+        let mut msg = Message::new();
+        msg.header.set_opcode(FlagOpcode::NOTIFY);
+        msg.header.set_aa(FlagAA::TRUE);
+        msg.add_question(Question::new(soa.name.clone(), FlagRecordType::SOA));
+        msg.add_resource(soa.clone());
+
+        match forwarder::send_message_to(msg, target.to_string(), key.as_ref()).await {
+            Ok(reply) => {
+                match reply.header.get_rcode() {
+                    Ok(FlagRCode::NOERROR) => info!("Notified {target} of zone {}", soa.name),
+                    rcode => warn!("{target} rejected NOTIFY of zone {}: {rcode:?}", soa.name),
+                }
+                return;
+            }
+            Err(err) => debug!("Attempt {attempt} to notify {target} failed: {err}"),
+        }
+    }
+
+    warn!("No answer from {target} to NOTIFY of zone {}", soa.name);
+}
+
+/// Answer a NOTIFY request, which triggers a refresh of the secondary zone
+///
+/// Only the primary of the zone is trusted to send it
+pub fn answer_notify(cfg: &Config, request: &Message, addr: SocketAddr) -> Message {
+    let mut reply = Message::reply_to(request);
+
+    let [question] = &request.questions[..] else {
+        reply.header.set_rcode(FlagRCode::FORMERR);
+        return reply;
+    };
+    let Some(secondary) = cfg.secondary.iter().find(|s| s.origin == question.name) else {
+        debug!(
+            "Ignore NOTIFY of {} from {addr}: not a secondary zone",
+            question.name
+        );
+        reply.header.set_rcode(FlagRCode::NOTAUTH);
+        return reply;
+    };
+    if secondary.primary.ip() != addr.ip() {
+        warn!(
+            "Refuse NOTIFY of {} from {addr}: not the primary",
+            question.name
+        );
+        reply.header.set_rcode(FlagRCode::REFUSED);
+        return reply;
+    }
+
+    info!("Received NOTIFY of zone {} from {addr}", question.name);
+    secondary::request_refresh(&secondary.origin);
+    reply
+}
@@ -1,4 +1,7 @@
+use std::fmt;
+
 use crate::common::{FlagClassCode, FlagRecordType, LabelSeq, ParseContext, SerializeContext};
+use crate::error::Error;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct Question {
@@ -24,12 +27,12 @@ impl Question {
         context.push(self.class_code as u8);
     }
 
-    pub fn parse(context: &mut ParseContext) -> Result<Question, *const str> {
+    pub fn parse(context: &mut ParseContext) -> Result<Question, Error> {
         let label_seq = LabelSeq::parse(context)?;
 
         let buff = context.current_slice();
         if buff.len() < 4 {
-            return Err("cannot read record type and class code");
+            return Err(Error::Truncated("record type and class code"));
         }
 
         let record_type = u16::from_be_bytes([buff[0], buff[1]]);
@@ -44,3 +47,15 @@ impl Question {
         Ok(question)
     }
 }
+
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t\t{}\t{}",
+            self.name,
+            FlagClassCode::from_bits_retain(self.class_code),
+            FlagRecordType::from_bits_retain(self.record_type)
+        )
+    }
+}
@@ -1,17 +1,26 @@
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 
 use crate::common::{FlagClassCode, FlagRecordType, LabelSeq, ParseContext, SerializeContext};
+use crate::edns::EdnsOption;
+use crate::error::Error;
+use crate::text::{self, parse_number, RecordHead, Token};
+use crate::tsig;
 use crate::utils::to_array;
 
 #[derive(Debug, Clone)]
 pub struct SOARecord {
-    mname: LabelSeq,
-    rname: LabelSeq,
-    serial: u32,
-    refresh: u32,
-    retry: u32,
-    expire: u32,
-    minimum: u32,
+    pub mname: LabelSeq,
+    pub rname: LabelSeq,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
 }
 
 const SOA_FIXED_SIZE: usize = 20;
@@ -37,13 +46,13 @@ impl SOARecord {
         context.extend_from_slice(&bytes);
     }
 
-    pub fn parse(context: &mut ParseContext) -> Result<SOARecord, *const str> {
+    pub fn parse(context: &mut ParseContext) -> Result<SOARecord, Error> {
         let mname = LabelSeq::parse(context)?;
         let rname = LabelSeq::parse(context)?;
 
         let slice = context.current_slice();
         if slice.len() < SOA_FIXED_SIZE {
-            return Err("cannot parse SOA record");
+            return Err(Error::Truncated("SOA record"));
         }
 
         let record = SOARecord {
@@ -61,6 +70,527 @@ impl SOARecord {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct MXRecord {
+    pub preference: u16,
+    pub exchange: LabelSeq,
+}
+
+impl MXRecord {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.preference.to_be_bytes());
+        self.exchange.serialize(context);
+    }
+
+    pub fn parse(context: &mut ParseContext) -> Result<MXRecord, Error> {
+        let slice = context.current_slice();
+        if slice.len() < 2 {
+            return Err(Error::Truncated("MX record"));
+        }
+        let preference = u16::from_be_bytes([slice[0], slice[1]]);
+        context.advance(2);
+
+        let exchange = LabelSeq::parse(context)?;
+        Ok(MXRecord {
+            preference,
+            exchange,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SRVRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: LabelSeq,
+}
+
+const SRV_FIXED_SIZE: usize = 6;
+
+impl SRVRecord {
+    /// Target must not be compressed, as per RFC 2782
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.priority.to_be_bytes());
+        context.extend_from_slice(&self.weight.to_be_bytes());
+        context.extend_from_slice(&self.port.to_be_bytes());
+        self.target.serialize_uncompressed(context);
+    }
+
+    pub fn parse(context: &mut ParseContext) -> Result<SRVRecord, Error> {
+        let slice = context.current_slice();
+        if slice.len() < SRV_FIXED_SIZE {
+            return Err(Error::Truncated("SRV record"));
+        }
+        let priority = u16::from_be_bytes([slice[0], slice[1]]);
+        let weight = u16::from_be_bytes([slice[2], slice[3]]);
+        let port = u16::from_be_bytes([slice[4], slice[5]]);
+        context.advance(SRV_FIXED_SIZE);
+
+        let target = LabelSeq::parse(context)?;
+        Ok(SRVRecord {
+            priority,
+            weight,
+            port,
+            target,
+        })
+    }
+}
+
+/// Transaction signature, as per RFC 8945 section 4.2
+#[derive(Debug, Clone)]
+pub struct TSIGRecord {
+    pub algorithm: LabelSeq,
+    /// Seconds since the epoch, on 48 bits
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: u16,
+    pub other: Vec<u8>,
+}
+
+/// Time signed, fudge, MAC size, original ID, error and other length
+const TSIG_FIXED_SIZE: usize = 16;
+
+impl TSIGRecord {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        self.algorithm.serialize_uncompressed(context);
+        context.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        context.extend_from_slice(&self.fudge.to_be_bytes());
+        context.extend_from_slice(&(self.mac.len() as u16).to_be_bytes());
+        context.extend_from_slice(&self.mac);
+        context.extend_from_slice(&self.original_id.to_be_bytes());
+        context.extend_from_slice(&self.error.to_be_bytes());
+        context.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        context.extend_from_slice(&self.other);
+    }
+
+    pub fn parse(context: &mut ParseContext) -> Result<TSIGRecord, Error> {
+        let algorithm = LabelSeq::parse(context)?;
+
+        let slice = context.current_slice();
+        if slice.len() < TSIG_FIXED_SIZE {
+            return Err(Error::Truncated("TSIG record"));
+        }
+        let mut time = [0; 8];
+        time[2..].copy_from_slice(&slice[0..6]);
+        let time_signed = u64::from_be_bytes(time);
+        let fudge = u16::from_be_bytes([slice[6], slice[7]]);
+        let mac_size = u16::from_be_bytes([slice[8], slice[9]]) as usize;
+        if slice.len() < TSIG_FIXED_SIZE + mac_size {
+            return Err(Error::Truncated("TSIG MAC"));
+        }
+        let mac = slice[10..10 + mac_size].to_vec();
+
+        let rest = &slice[10 + mac_size..];
+        let original_id = u16::from_be_bytes([rest[0], rest[1]]);
+        let error = u16::from_be_bytes([rest[2], rest[3]]);
+        let other_len = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+        if rest.len() < 6 + other_len {
+            return Err(Error::Truncated("TSIG other data"));
+        }
+        let other = rest[6..6 + other_len].to_vec();
+        context.advance(TSIG_FIXED_SIZE + mac_size + other_len);
+
+        Ok(TSIGRecord {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+}
+
+/// Zone Key flag of DNSKEY records, set on keys that sign the zone
+pub const DNSKEY_FLAG_ZONE: u16 = 0x0100;
+/// Secure Entry Point flag of DNSKEY records, set on key signing keys as per RFC 4034
+pub const DNSKEY_FLAG_SEP: u16 = 0x0001;
+/// The only valid protocol of DNSKEY records
+pub const DNSKEY_PROTOCOL: u8 = 3;
+
+/// Public key of a zone, as per RFC 4034 section 2
+#[derive(Debug, Clone)]
+pub struct DNSKEYRecord {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+const DNSKEY_FIXED_SIZE: usize = 4;
+
+impl DNSKEYRecord {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.flags.to_be_bytes());
+        context.push(self.protocol);
+        context.push(self.algorithm);
+        context.extend_from_slice(&self.public_key);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<DNSKEYRecord, Error> {
+        if size < DNSKEY_FIXED_SIZE {
+            return Err(Error::Truncated("DNSKEY record"));
+        }
+        let slice = context.current_slice();
+        let record = DNSKEYRecord {
+            flags: u16::from_be_bytes([slice[0], slice[1]]),
+            protocol: slice[2],
+            algorithm: slice[3],
+            public_key: slice[DNSKEY_FIXED_SIZE..size].to_vec(),
+        };
+        context.advance(size);
+        Ok(record)
+    }
+
+    /// Tag identifying the key in RRSIG and DS records, as per RFC 4034 appendix B
+    pub fn key_tag(&self) -> u16 {
+        let mut context = SerializeContext::new();
+        self.serialize(&mut context);
+        let rdata = context.to_vec();
+
+        // RSA/MD5 keys use the low bits of the modulus instead
+        if self.algorithm == 1 {
+            let n = rdata.len();
+            if n < DNSKEY_FIXED_SIZE + 3 {
+                return 0;
+            }
+            return u16::from_be_bytes([rdata[n - 3], rdata[n - 2]]);
+        }
+
+        let mut sum: u32 = 0;
+        for (i, byte) in rdata.iter().enumerate() {
+            sum += if i % 2 == 0 {
+                (*byte as u32) << 8
+            } else {
+                *byte as u32
+            };
+        }
+        sum += (sum >> 16) & 0xffff;
+        (sum & 0xffff) as u16
+    }
+}
+
+/// Digest of the DNSKEY of a child zone, held by its parent, as per RFC 4034 section 5
+#[derive(Debug, Clone)]
+pub struct DSRecord {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+const DS_FIXED_SIZE: usize = 4;
+
+impl DSRecord {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.key_tag.to_be_bytes());
+        context.push(self.algorithm);
+        context.push(self.digest_type);
+        context.extend_from_slice(&self.digest);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<DSRecord, Error> {
+        if size < DS_FIXED_SIZE {
+            return Err(Error::Truncated("DS record"));
+        }
+        let slice = context.current_slice();
+        let record = DSRecord {
+            key_tag: u16::from_be_bytes([slice[0], slice[1]]),
+            algorithm: slice[2],
+            digest_type: slice[3],
+            digest: slice[DS_FIXED_SIZE..size].to_vec(),
+        };
+        context.advance(size);
+        Ok(record)
+    }
+}
+
+/// Signature of an RRset, as per RFC 4034 section 3
+#[derive(Debug, Clone)]
+pub struct RRSIGRecord {
+    pub type_covered: FlagRecordType,
+    pub algorithm: u8,
+    /// Labels of the owner, not counting the root nor a leading wildcard
+    pub labels: u8,
+    pub original_ttl: u32,
+    /// Seconds since the epoch, in serial number arithmetic
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer_name: LabelSeq,
+    pub signature: Vec<u8>,
+}
+
+/// Type covered, algorithm, labels, original TTL, expiration, inception and key tag
+const RRSIG_FIXED_SIZE: usize = 18;
+
+impl RRSIGRecord {
+    /// Everything but the signature, which is what the signature covers along with the RRset
+    pub fn serialize_without_signature(&self, context: &mut SerializeContext) {
+        context.extend_from_slice(&self.type_covered.bits().to_be_bytes());
+        context.push(self.algorithm);
+        context.push(self.labels);
+        context.extend_from_slice(&self.original_ttl.to_be_bytes());
+        context.extend_from_slice(&self.expiration.to_be_bytes());
+        context.extend_from_slice(&self.inception.to_be_bytes());
+        context.extend_from_slice(&self.key_tag.to_be_bytes());
+        self.signer_name.serialize_uncompressed(context);
+    }
+
+    /// Signer name must not be compressed, as per RFC 4034 section 3.1.7
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        self.serialize_without_signature(context);
+        context.extend_from_slice(&self.signature);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<RRSIGRecord, Error> {
+        let max_index = context.current_idx() + size;
+        let slice = context.current_slice();
+        if size < RRSIG_FIXED_SIZE {
+            return Err(Error::Truncated("RRSIG record"));
+        }
+        let type_covered =
+            FlagRecordType::from_bits_retain(u16::from_be_bytes([slice[0], slice[1]]));
+        let algorithm = slice[2];
+        let labels = slice[3];
+        let original_ttl = u32::from_be_bytes(to_array(&slice[4..8]));
+        let expiration = u32::from_be_bytes(to_array(&slice[8..12]));
+        let inception = u32::from_be_bytes(to_array(&slice[12..16]));
+        let key_tag = u16::from_be_bytes([slice[16], slice[17]]);
+        context.advance(RRSIG_FIXED_SIZE);
+
+        let signer_name = LabelSeq::parse(context)?;
+        let Some(signature_size) = max_index.checked_sub(context.current_idx()) else {
+            return Err(Error::BadRData(
+                "sequence in record exceed specified length",
+            ));
+        };
+        let signature = context.current_slice()[..signature_size].to_vec();
+        context.advance(signature_size);
+
+        Ok(RRSIGRecord {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        })
+    }
+}
+
+/// Next owner name of a zone and the types present at this one, as per RFC 4034 section 4
+#[derive(Debug, Clone)]
+pub struct NSECRecord {
+    pub next_domain: LabelSeq,
+    pub types: Vec<FlagRecordType>,
+}
+
+impl NSECRecord {
+    /// Next domain name must not be compressed, as per RFC 4034 section 4.1.1
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        self.next_domain.serialize_uncompressed(context);
+        serialize_type_bitmap(&self.types, context);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<NSECRecord, Error> {
+        let max_index = context.current_idx() + size;
+        let next_domain = LabelSeq::parse(context)?;
+        let Some(bitmap_size) = max_index.checked_sub(context.current_idx()) else {
+            return Err(Error::BadRData(
+                "sequence in record exceed specified length",
+            ));
+        };
+        let types = parse_type_bitmap(&context.current_slice()[..bitmap_size])?;
+        context.advance(bitmap_size);
+        Ok(NSECRecord { next_domain, types })
+    }
+}
+
+/// Hash algorithm of NSEC3 records, the only one defined by RFC 5155
+pub const NSEC3_HASH_SHA1: u8 = 1;
+/// Opt-Out flag of NSEC3 records
+pub const NSEC3_FLAG_OPT_OUT: u8 = 0x01;
+
+/// Next hashed owner name of a zone and the types present at this one,
+/// as per RFC 5155 section 3
+#[derive(Debug, Clone)]
+pub struct NSEC3Record {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed: Vec<u8>,
+    pub types: Vec<FlagRecordType>,
+}
+
+impl NSEC3Record {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.push(self.hash_algorithm);
+        context.push(self.flags);
+        context.extend_from_slice(&self.iterations.to_be_bytes());
+        context.push(self.salt.len() as u8);
+        context.extend_from_slice(&self.salt);
+        context.push(self.next_hashed.len() as u8);
+        context.extend_from_slice(&self.next_hashed);
+        serialize_type_bitmap(&self.types, context);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<NSEC3Record, Error> {
+        let slice = &context.current_slice()[..size];
+        let param = NSEC3PARAMRecord::parse_fields(slice, "NSEC3 record")?;
+        let rest = &slice[NSEC3PARAM_FIXED_SIZE + param.salt.len()..];
+        let Some((&hash_len, rest)) = rest.split_first() else {
+            return Err(Error::Truncated("NSEC3 next hashed owner"));
+        };
+        let hash_len = hash_len as usize;
+        if hash_len == 0 || rest.len() < hash_len {
+            return Err(Error::BadRData("NSEC3 next hashed owner is invalid"));
+        }
+        let next_hashed = rest[..hash_len].to_vec();
+        let types = parse_type_bitmap(&rest[hash_len..])?;
+        context.advance(size);
+
+        Ok(NSEC3Record {
+            hash_algorithm: param.hash_algorithm,
+            flags: param.flags,
+            iterations: param.iterations,
+            salt: param.salt,
+            next_hashed,
+            types,
+        })
+    }
+}
+
+/// Parameters a zone uses for its NSEC3 chain, as per RFC 5155 section 4
+#[derive(Debug, Clone)]
+pub struct NSEC3PARAMRecord {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+}
+
+/// Hash algorithm, flags, iterations and salt length
+const NSEC3PARAM_FIXED_SIZE: usize = 5;
+
+impl NSEC3PARAMRecord {
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        context.push(self.hash_algorithm);
+        context.push(self.flags);
+        context.extend_from_slice(&self.iterations.to_be_bytes());
+        context.push(self.salt.len() as u8);
+        context.extend_from_slice(&self.salt);
+    }
+
+    pub fn parse(context: &mut ParseContext, size: usize) -> Result<NSEC3PARAMRecord, Error> {
+        let slice = &context.current_slice()[..size];
+        let record = Self::parse_fields(slice, "NSEC3PARAM record")?;
+        if NSEC3PARAM_FIXED_SIZE + record.salt.len() != size {
+            return Err(Error::BadRData(
+                "sequence in record exceed specified length",
+            ));
+        }
+        context.advance(size);
+        Ok(record)
+    }
+
+    /// Read the fields NSEC3 records start with
+    fn parse_fields(slice: &[u8], what: &'static str) -> Result<NSEC3PARAMRecord, Error> {
+        if slice.len() < NSEC3PARAM_FIXED_SIZE {
+            return Err(Error::Truncated(what));
+        }
+        let salt_len = slice[4] as usize;
+        if slice.len() < NSEC3PARAM_FIXED_SIZE + salt_len {
+            return Err(Error::Truncated("NSEC3 salt"));
+        }
+        Ok(NSEC3PARAMRecord {
+            hash_algorithm: slice[0],
+            flags: slice[1],
+            iterations: u16::from_be_bytes([slice[2], slice[3]]),
+            salt: slice[NSEC3PARAM_FIXED_SIZE..NSEC3PARAM_FIXED_SIZE + salt_len].to_vec(),
+        })
+    }
+}
+
+/// Write types as the windowed bitmaps of RFC 4034 section 4.1.2
+fn serialize_type_bitmap(types: &[FlagRecordType], context: &mut SerializeContext) {
+    let mut values: Vec<u16> = types.iter().map(|t| t.bits()).collect();
+    values.sort_unstable();
+    values.dedup();
+
+    for window in values.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let last = (window[window.len() - 1] & 0xff) as usize;
+        let mut bitmap = vec![0u8; last / 8 + 1];
+        for value in window {
+            let bit = (value & 0xff) as usize;
+            bitmap[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        context.push((window[0] >> 8) as u8);
+        context.push(bitmap.len() as u8);
+        context.extend_from_slice(&bitmap);
+    }
+}
+
+/// Read the windowed bitmaps of RFC 4034 section 4.1.2, which must come in increasing order
+fn parse_type_bitmap(mut bytes: &[u8]) -> Result<Vec<FlagRecordType>, Error> {
+    let mut types = vec![];
+    let mut last_window = None;
+    while !bytes.is_empty() {
+        let [window, len, rest @ ..] = bytes else {
+            return Err(Error::Truncated("type bitmap"));
+        };
+        let len = *len as usize;
+        if !(1..=32).contains(&len) || rest.len() < len {
+            return Err(Error::BadRData("type bitmap has invalid length"));
+        }
+        if last_window.is_some_and(|last| last >= *window) {
+            return Err(Error::BadRData("type bitmap windows out of order"));
+        }
+        last_window = Some(*window);
+
+        for (i, byte) in rest[..len].iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let value = (*window as u16) << 8 | (i * 8 + bit) as u16;
+                    types.push(FlagRecordType::from_bits_retain(value));
+                }
+            }
+        }
+        bytes = &rest[len..];
+    }
+    Ok(types)
+}
+
+/// Parse the character-strings of a TXT record spanning the next `size` bytes
+fn parse_character_strings(context: &mut ParseContext, size: usize) -> Result<Vec<Vec<u8>>, Error> {
+    if size == 0 {
+        return Err(Error::BadRData("TXT record must have at least one string"));
+    }
+
+    let buff = context.current_slice();
+    let mut strings = vec![];
+    let mut i = 0;
+    while i < size {
+        let len = buff[i] as usize;
+        if i + 1 + len > size {
+            return Err(Error::BadRData("character-string exceed specified length"));
+        }
+        strings.push(buff[i + 1..i + 1 + len].to_vec());
+        i += 1 + len;
+    }
+
+    context.advance(size);
+    Ok(strings)
+}
+
 #[derive(Debug, Clone)]
 pub enum ResourceData {
     A(Ipv4Addr),
@@ -68,6 +598,461 @@ pub enum ResourceData {
     CNAME(LabelSeq),
     AAAA(Ipv6Addr),
     SOA(SOARecord),
+    PTR(LabelSeq),
+    MX(MXRecord),
+    /// One or more character-strings
+    TXT(Vec<Vec<u8>>),
+    SRV(SRVRecord),
+    OPT(Vec<EdnsOption>),
+    DS(DSRecord),
+    RRSIG(RRSIGRecord),
+    NSEC(NSECRecord),
+    DNSKEY(DNSKEYRecord),
+    NSEC3(NSEC3Record),
+    NSEC3PARAM(NSEC3PARAMRecord),
+    TSIG(TSIGRecord),
+    /// Record of a type this server does not know, kept as is as per RFC 3597.
+    /// Also the empty data of update requests naming a whole RRset
+    Unknown {
+        rtype: u16,
+        rdata: Vec<u8>,
+    },
+}
+
+impl ResourceData {
+    /// Write rdata in the generic `\# len hex` form of RFC 3597 section 5
+    fn fmt_generic(rdata: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\\# {}", rdata.len())?;
+        if !rdata.is_empty() {
+            write!(f, " {}", text::to_hex(rdata))?;
+        }
+        Ok(())
+    }
+
+    /// Write the mnemonics of a type bitmap, separated by spaces
+    fn fmt_types(types: &[FlagRecordType], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, record_type) in types.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{record_type}")?;
+        }
+        Ok(())
+    }
+
+    /// Write a salt of NSEC3 records, `-` standing for an empty one
+    fn fmt_salt(salt: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if salt.is_empty() {
+            write!(f, "-")
+        } else {
+            write!(f, "{}", text::to_hex(salt))
+        }
+    }
+
+    /// Write a quoted character-string, escaping as per RFC 1035 section 5.1
+    fn fmt_character_string(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for byte in bytes {
+            match byte {
+                b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                0x20..=0x7e => write!(f, "{}", *byte as char)?,
+                _ => write!(f, "\\{byte:03}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+impl ResourceData {
+    pub fn record_type(&self) -> FlagRecordType {
+        match self {
+            ResourceData::A(_) => FlagRecordType::A,
+            ResourceData::NS(_) => FlagRecordType::NS,
+            ResourceData::CNAME(_) => FlagRecordType::CNAME,
+            ResourceData::AAAA(_) => FlagRecordType::AAAA,
+            ResourceData::SOA(_) => FlagRecordType::SOA,
+            ResourceData::PTR(_) => FlagRecordType::PTR,
+            ResourceData::MX(_) => FlagRecordType::MX,
+            ResourceData::TXT(_) => FlagRecordType::TXT,
+            ResourceData::SRV(_) => FlagRecordType::SRV,
+            ResourceData::OPT(_) => FlagRecordType::OPT,
+            ResourceData::DS(_) => FlagRecordType::DS,
+            ResourceData::RRSIG(_) => FlagRecordType::RRSIG,
+            ResourceData::NSEC(_) => FlagRecordType::NSEC,
+            ResourceData::DNSKEY(_) => FlagRecordType::DNSKEY,
+            ResourceData::NSEC3(_) => FlagRecordType::NSEC3,
+            ResourceData::NSEC3PARAM(_) => FlagRecordType::NSEC3PARAM,
+            ResourceData::TSIG(_) => FlagRecordType::TSIG,
+            ResourceData::Unknown { rtype, .. } => FlagRecordType::from_bits_retain(*rtype),
+        }
+    }
+
+    pub fn serialize(&self, context: &mut SerializeContext) {
+        match self {
+            ResourceData::A(ip) => {
+                let mut ip = ip.octets().to_vec();
+                context.append(&mut ip)
+            }
+            ResourceData::AAAA(ip) => {
+                let mut ip = ip.octets().to_vec();
+                context.append(&mut ip)
+            }
+            ResourceData::SOA(soa) => soa.serialize(context),
+            ResourceData::NS(seq) | ResourceData::CNAME(seq) | ResourceData::PTR(seq) => {
+                seq.serialize(context)
+            }
+            ResourceData::MX(mx) => mx.serialize(context),
+            ResourceData::TXT(strings) => {
+                for s in strings {
+                    context.push(s.len() as u8);
+                    context.extend_from_slice(s);
+                }
+            }
+            ResourceData::SRV(srv) => srv.serialize(context),
+            ResourceData::OPT(options) => {
+                for option in options {
+                    option.serialize(context);
+                }
+            }
+            ResourceData::DS(ds) => ds.serialize(context),
+            ResourceData::RRSIG(rrsig) => rrsig.serialize(context),
+            ResourceData::NSEC(nsec) => nsec.serialize(context),
+            ResourceData::DNSKEY(dnskey) => dnskey.serialize(context),
+            ResourceData::NSEC3(nsec3) => nsec3.serialize(context),
+            ResourceData::NSEC3PARAM(param) => param.serialize(context),
+            ResourceData::TSIG(tsig) => tsig.serialize(context),
+            ResourceData::Unknown { rdata, .. } => context.extend_from_slice(rdata),
+        }
+    }
+}
+
+impl ResourceData {
+    /// Same data with the names of the types listed in RFC 4034 section 6.2 lowercased,
+    /// leaving out NSEC as per RFC 6840 section 5.1
+    pub fn to_canonical(&self) -> ResourceData {
+        match self {
+            ResourceData::NS(name) => ResourceData::NS(name.to_lowercase()),
+            ResourceData::CNAME(name) => ResourceData::CNAME(name.to_lowercase()),
+            ResourceData::PTR(name) => ResourceData::PTR(name.to_lowercase()),
+            ResourceData::SOA(soa) => ResourceData::SOA(SOARecord {
+                mname: soa.mname.to_lowercase(),
+                rname: soa.rname.to_lowercase(),
+                ..soa.clone()
+            }),
+            ResourceData::MX(mx) => ResourceData::MX(MXRecord {
+                exchange: mx.exchange.to_lowercase(),
+                ..mx.clone()
+            }),
+            ResourceData::SRV(srv) => ResourceData::SRV(SRVRecord {
+                target: srv.target.to_lowercase(),
+                ..srv.clone()
+            }),
+            ResourceData::RRSIG(rrsig) => ResourceData::RRSIG(RRSIGRecord {
+                signer_name: rrsig.signer_name.to_lowercase(),
+                ..rrsig.clone()
+            }),
+            data => data.clone(),
+        }
+    }
+}
+
+impl ResourceData {
+    /// Read resource data of the given type from its presentation format
+    ///
+    /// Any type may also be given in the generic `\\# len hex` form of RFC 3597 section 5
+    pub fn from_tokens(
+        record_type: FlagRecordType,
+        tokens: &[Token],
+        origin: &LabelSeq,
+    ) -> Result<ResourceData, Error> {
+        if tokens.first().is_some_and(|t| !t.quoted && t.text == "\\#") {
+            return Self::from_generic(record_type, &tokens[1..]);
+        }
+
+        let mut iter = tokens.iter();
+        let name = |token: Option<&Token>, what: &str| -> Result<LabelSeq, Error> {
+            let token = token.ok_or_else(|| Error::BadSyntax(format!("missing {what}")))?;
+            LabelSeq::from_text(&token.text, origin)
+        };
+
+        let data = match record_type {
+            FlagRecordType::A => ResourceData::A(parse_number(iter.next(), "IPv4 address")?),
+            FlagRecordType::AAAA => ResourceData::AAAA(parse_number(iter.next(), "IPv6 address")?),
+            FlagRecordType::NS => ResourceData::NS(name(iter.next(), "name server")?),
+            FlagRecordType::CNAME => ResourceData::CNAME(name(iter.next(), "canonical name")?),
+            FlagRecordType::PTR => ResourceData::PTR(name(iter.next(), "domain name")?),
+            FlagRecordType::SOA => ResourceData::SOA(SOARecord {
+                mname: name(iter.next(), "primary name server")?,
+                rname: name(iter.next(), "mailbox")?,
+                serial: parse_number(iter.next(), "serial")?,
+                refresh: parse_ttl_token(iter.next(), "refresh")?,
+                retry: parse_ttl_token(iter.next(), "retry")?,
+                expire: parse_ttl_token(iter.next(), "expire")?,
+                minimum: parse_ttl_token(iter.next(), "minimum")?,
+            }),
+            FlagRecordType::MX => ResourceData::MX(MXRecord {
+                preference: parse_number(iter.next(), "preference")?,
+                exchange: name(iter.next(), "exchange")?,
+            }),
+            FlagRecordType::SRV => ResourceData::SRV(SRVRecord {
+                priority: parse_number(iter.next(), "priority")?,
+                weight: parse_number(iter.next(), "weight")?,
+                port: parse_number(iter.next(), "port")?,
+                target: name(iter.next(), "target")?,
+            }),
+            FlagRecordType::TXT => {
+                let mut strings = vec![];
+                for token in iter.by_ref() {
+                    let bytes = text::unescape(&token.text)?;
+                    if bytes.len() > u8::MAX as usize {
+                        return Err(Error::BadSyntax("character-string too long".to_string()));
+                    }
+                    strings.push(bytes);
+                }
+                if strings.is_empty() {
+                    return Err(Error::BadSyntax("missing character-string".to_string()));
+                }
+                ResourceData::TXT(strings)
+            }
+            FlagRecordType::DS => ResourceData::DS(DSRecord {
+                key_tag: parse_number(iter.next(), "key tag")?,
+                algorithm: parse_number(iter.next(), "algorithm")?,
+                digest_type: parse_number(iter.next(), "digest type")?,
+                digest: {
+                    let hex: String = iter.by_ref().map(|t| t.text.as_str()).collect();
+                    if hex.is_empty() {
+                        return Err(Error::BadSyntax("missing digest".to_string()));
+                    }
+                    text::parse_hex(&hex)?
+                },
+            }),
+            FlagRecordType::RRSIG => ResourceData::RRSIG(RRSIGRecord {
+                type_covered: record_type_token(iter.next(), "type covered")?,
+                algorithm: parse_number(iter.next(), "algorithm")?,
+                labels: parse_number(iter.next(), "labels")?,
+                original_ttl: parse_ttl_token(iter.next(), "original TTL")?,
+                expiration: timestamp_token(iter.next(), "expiration")?,
+                inception: timestamp_token(iter.next(), "inception")?,
+                key_tag: parse_number(iter.next(), "key tag")?,
+                signer_name: name(iter.next(), "signer name")?,
+                signature: text::parse_base64(iter.by_ref(), "signature")?,
+            }),
+            FlagRecordType::NSEC => ResourceData::NSEC(NSECRecord {
+                next_domain: name(iter.next(), "next domain name")?,
+                types: types_tokens(iter.by_ref())?,
+            }),
+            FlagRecordType::DNSKEY => ResourceData::DNSKEY(DNSKEYRecord {
+                flags: parse_number(iter.next(), "flags")?,
+                protocol: parse_number(iter.next(), "protocol")?,
+                algorithm: parse_number(iter.next(), "algorithm")?,
+                public_key: text::parse_base64(iter.by_ref(), "public key")?,
+            }),
+            FlagRecordType::NSEC3 => ResourceData::NSEC3(NSEC3Record {
+                hash_algorithm: parse_number(iter.next(), "hash algorithm")?,
+                flags: parse_number(iter.next(), "flags")?,
+                iterations: parse_number(iter.next(), "iterations")?,
+                salt: salt_token(iter.next())?,
+                next_hashed: {
+                    let token = iter.next().ok_or_else(|| {
+                        Error::BadSyntax("missing next hashed owner name".to_string())
+                    })?;
+                    text::parse_base32hex(&token.text)?
+                },
+                types: types_tokens(iter.by_ref())?,
+            }),
+            FlagRecordType::NSEC3PARAM => ResourceData::NSEC3PARAM(NSEC3PARAMRecord {
+                hash_algorithm: parse_number(iter.next(), "hash algorithm")?,
+                flags: parse_number(iter.next(), "flags")?,
+                iterations: parse_number(iter.next(), "iterations")?,
+                salt: salt_token(iter.next())?,
+            }),
+            FlagRecordType::OPT | FlagRecordType::TSIG => {
+                return Err(Error::BadSyntax(format!(
+                    "{record_type} pseudo-record has no presentation format"
+                )))
+            }
+            _ => {
+                return Err(Error::BadSyntax(format!(
+                    "record type {record_type} must be given in generic form"
+                )))
+            }
+        };
+
+        if let Some(token) = iter.next() {
+            return Err(Error::BadSyntax(format!(
+                "unexpected {:?} after {record_type} data",
+                token.text
+            )));
+        }
+        Ok(data)
+    }
+
+    fn from_generic(record_type: FlagRecordType, tokens: &[Token]) -> Result<ResourceData, Error> {
+        let length: usize = parse_number(tokens.first(), "generic data length")?;
+
+        let hex: String = tokens[1..].iter().map(|t| t.text.as_str()).collect();
+        if hex.len() != length * 2 {
+            return Err(Error::BadSyntax(format!(
+                "generic data does not have {length} bytes"
+            )));
+        }
+        let rdata = text::parse_hex(&hex)?;
+
+        // Known types are read from the wire format, so they end up the same as if received
+        let mut context = ParseContext::new(rdata);
+        let data = ResourceRecord::parse_data(&mut context, record_type, length)?;
+        if !context.current_slice().is_empty() {
+            return Err(Error::BadRData("generic data exceeds the record"));
+        }
+        Ok(data)
+    }
+}
+
+fn parse_ttl_token(token: Option<&Token>, what: &str) -> Result<u32, Error> {
+    let token = token.ok_or_else(|| Error::BadSyntax(format!("missing {what}")))?;
+    text::parse_ttl(&token.text)
+}
+
+fn record_type_token(token: Option<&Token>, what: &str) -> Result<FlagRecordType, Error> {
+    let token = token.ok_or_else(|| Error::BadSyntax(format!("missing {what}")))?;
+    token.text.parse()
+}
+
+fn timestamp_token(token: Option<&Token>, what: &str) -> Result<u32, Error> {
+    let token = token.ok_or_else(|| Error::BadSyntax(format!("missing {what}")))?;
+    text::parse_timestamp(&token.text)
+}
+
+fn salt_token(token: Option<&Token>) -> Result<Vec<u8>, Error> {
+    let token = token.ok_or_else(|| Error::BadSyntax("missing salt".to_string()))?;
+    if token.text == "-" {
+        return Ok(vec![]);
+    }
+    let salt = text::parse_hex(&token.text)?;
+    if salt.len() > u8::MAX as usize {
+        return Err(Error::BadSyntax("salt too long".to_string()));
+    }
+    Ok(salt)
+}
+
+/// Read the mnemonics of a type bitmap, which may be empty
+fn types_tokens<'a>(tokens: impl Iterator<Item = &'a Token>) -> Result<Vec<FlagRecordType>, Error> {
+    tokens.map(|token| token.text.parse()).collect()
+}
+
+impl fmt::Display for ResourceData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceData::A(ip) => write!(f, "{ip}"),
+            ResourceData::AAAA(ip) => write!(f, "{ip}"),
+            ResourceData::NS(seq) | ResourceData::CNAME(seq) | ResourceData::PTR(seq) => {
+                write!(f, "{seq}")
+            }
+            ResourceData::MX(mx) => write!(f, "{} {}", mx.preference, mx.exchange),
+            ResourceData::TXT(strings) => {
+                for (i, s) in strings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    Self::fmt_character_string(s, f)?;
+                }
+                Ok(())
+            }
+            ResourceData::SRV(srv) => write!(
+                f,
+                "{} {} {} {}",
+                srv.priority, srv.weight, srv.port, srv.target
+            ),
+            ResourceData::SOA(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                soa.mname, soa.rname, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+            ),
+            ResourceData::OPT(options) => {
+                let mut context = SerializeContext::new();
+                for option in options {
+                    option.serialize(&mut context);
+                }
+                Self::fmt_generic(&context.to_vec(), f)
+            }
+            ResourceData::DS(ds) => write!(
+                f,
+                "{} {} {} {}",
+                ds.key_tag,
+                ds.algorithm,
+                ds.digest_type,
+                text::to_hex(&ds.digest)
+            ),
+            ResourceData::RRSIG(rrsig) => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                rrsig.type_covered,
+                rrsig.algorithm,
+                rrsig.labels,
+                rrsig.original_ttl,
+                text::format_timestamp(rrsig.expiration),
+                text::format_timestamp(rrsig.inception),
+                rrsig.key_tag,
+                rrsig.signer_name,
+                BASE64.encode(&rrsig.signature)
+            ),
+            ResourceData::NSEC(nsec) => {
+                write!(f, "{}", nsec.next_domain)?;
+                if !nsec.types.is_empty() {
+                    write!(f, " ")?;
+                }
+                Self::fmt_types(&nsec.types, f)
+            }
+            ResourceData::DNSKEY(dnskey) => write!(
+                f,
+                "{} {} {} {}",
+                dnskey.flags,
+                dnskey.protocol,
+                dnskey.algorithm,
+                BASE64.encode(&dnskey.public_key)
+            ),
+            ResourceData::NSEC3(nsec3) => {
+                write!(
+                    f,
+                    "{} {} {} ",
+                    nsec3.hash_algorithm, nsec3.flags, nsec3.iterations
+                )?;
+                Self::fmt_salt(&nsec3.salt, f)?;
+                write!(f, " {}", text::to_base32hex(&nsec3.next_hashed))?;
+                if !nsec3.types.is_empty() {
+                    write!(f, " ")?;
+                }
+                Self::fmt_types(&nsec3.types, f)
+            }
+            ResourceData::NSEC3PARAM(param) => {
+                write!(
+                    f,
+                    "{} {} {} ",
+                    param.hash_algorithm, param.flags, param.iterations
+                )?;
+                Self::fmt_salt(&param.salt, f)
+            }
+            ResourceData::TSIG(tsig) => {
+                write!(
+                    f,
+                    "{} {} {} {} {} {} {} {}",
+                    tsig.algorithm,
+                    tsig.time_signed,
+                    tsig.fudge,
+                    tsig.mac.len(),
+                    BASE64.encode(&tsig.mac),
+                    tsig.original_id,
+                    tsig::error_name(tsig.error),
+                    tsig.other.len()
+                )?;
+                if !tsig.other.is_empty() {
+                    write!(f, " {}", BASE64.encode(&tsig.other))?;
+                }
+                Ok(())
+            }
+            ResourceData::Unknown { rdata, .. } => Self::fmt_generic(rdata, f),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,11 +1061,24 @@ pub struct ResourceRecord {
     pub record_type: FlagRecordType,
     pub class_code: FlagClassCode,
     pub ttl: u32,
-    pub length: u16,
     pub data: ResourceData,
 }
 
 impl ResourceRecord {
+    /// Record of class IN, with type derived from the data
+    pub fn new(name: LabelSeq, ttl: u32, data: ResourceData) -> ResourceRecord {
+        ResourceRecord {
+            name,
+            record_type: data.record_type(),
+            class_code: FlagClassCode::IN,
+            ttl,
+            data,
+        }
+    }
+
+    /// RDLENGTH is not known until the data is written, since compression of names
+    /// inside it depends on what was written before.
+    /// Therefore a slot is reserved for it, then filled after the data
     pub fn serialize(&self, context: &mut SerializeContext) {
         self.name.serialize(context);
 
@@ -96,53 +1094,81 @@ impl ResourceRecord {
             (self.ttl >> 16) as u8,
             (self.ttl >> 8) as u8,
             self.ttl as u8,
-            (self.length >> 8) as u8,
-            self.length as u8,
+            0,
+            0,
         ];
         context.append(&mut rest);
 
-        match &self.data {
-            ResourceData::A(ip) => {
-                let mut ip = ip.octets().to_vec();
-                context.append(&mut ip)
-            }
-            ResourceData::AAAA(ip) => {
-                let mut ip = ip.octets().to_vec();
-                context.append(&mut ip)
-            }
-            ResourceData::SOA(soa) => soa.serialize(context),
-            ResourceData::NS(seq) | ResourceData::CNAME(seq) => seq.serialize(context),
-        }
+        let length_idx = context.len() - 2;
+        self.data.serialize(context);
+        let length = context.len() - length_idx - 2;
+        context.set_u16_at(length_idx, length as u16);
     }
 
-    pub fn parse(context: &mut ParseContext) -> Result<ResourceRecord, *const str> {
+    pub fn parse(context: &mut ParseContext) -> Result<ResourceRecord, Error> {
         let name = LabelSeq::parse(context)?;
 
         let data = context.current_slice();
         if data.len() < 10 {
-            return Err("cannot parse record");
+            return Err(Error::Truncated("resource record"));
         }
 
         let flag = u16::from_be_bytes([data[0], data[1]]);
-        let record_type: FlagRecordType =
-            FlagRecordType::from_bits(flag).expect("cannot parse record type");
+        let record_type = FlagRecordType::from_bits_retain(flag);
 
         let flag = u16::from_be_bytes([data[2], data[3]]);
-        let class_code: FlagClassCode =
-            FlagClassCode::from_bits(flag).expect("cannot parse class code");
+        // Class of OPT pseudo-record holds the UDP payload size
+        let class_code = if record_type == FlagRecordType::OPT {
+            FlagClassCode::from_bits_retain(flag)
+        } else {
+            FlagClassCode::from_bits(flag).ok_or(Error::UnknownClass(flag))?
+        };
 
         let ttl = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
         let length = u16::from_be_bytes([data[8], data[9]]);
 
         context.advance(10);
-        let data = Self::parse_data(context, record_type.clone(), length as usize)?;
+        // Update requests have records without data to name a whole RRset,
+        // as per RFC 2136 section 2.4
+        let is_update_class = class_code == FlagClassCode::ANY || class_code == FlagClassCode::NONE;
+        let data = if length == 0 && is_update_class {
+            ResourceData::Unknown {
+                rtype: record_type.bits(),
+                rdata: vec![],
+            }
+        } else {
+            Self::parse_data(context, record_type.clone(), length as usize)?
+        };
 
         Ok(ResourceRecord {
             name,
             record_type,
             class_code,
             ttl,
-            length,
+            data,
+        })
+    }
+
+    /// Read a record from the tokens of one entry:
+    /// owner, TTL and class in any order, type, then the data.
+    /// The class defaults to IN
+    pub fn from_tokens(tokens: &[Token], origin: &LabelSeq) -> Result<ResourceRecord, Error> {
+        let mut iter = tokens.iter();
+        let owner = iter
+            .next()
+            .ok_or_else(|| Error::BadSyntax("missing owner name".to_string()))?;
+        let name = LabelSeq::from_text(&owner.text, origin)?;
+
+        let (head, rest) = RecordHead::parse(iter.as_slice())?;
+        let ttl = head
+            .ttl
+            .ok_or_else(|| Error::BadSyntax("missing TTL".to_string()))?;
+        let data = ResourceData::from_tokens(head.record_type.clone(), rest, origin)?;
+        Ok(ResourceRecord {
+            name,
+            record_type: head.record_type,
+            class_code: head.class_code.unwrap_or(FlagClassCode::IN),
+            ttl,
             data,
         })
     }
@@ -151,10 +1177,10 @@ impl ResourceRecord {
         context: &mut ParseContext,
         record_type: FlagRecordType,
         size: usize,
-    ) -> Result<ResourceData, *const str> {
+    ) -> Result<ResourceData, Error> {
         let buff = context.current_slice();
         if buff.len() < size {
-            return Err("cannot parse resource data");
+            return Err(Error::Truncated("resource data"));
         }
 
         let max_index = context.current_idx() + size;
@@ -162,7 +1188,7 @@ impl ResourceRecord {
         let res = match record_type {
             FlagRecordType::A => {
                 if size != 4 {
-                    return Err("ipv4 must be 4 bytes");
+                    return Err(Error::BadRData("ipv4 must be 4 bytes"));
                 }
                 let ip = Ipv4Addr::from([buff[0], buff[1], buff[2], buff[3]]);
                 context.advance(4);
@@ -170,7 +1196,7 @@ impl ResourceRecord {
             }
             FlagRecordType::AAAA => {
                 if size != 16 {
-                    return Err("ipv6 must be 16 bytes");
+                    return Err(Error::BadRData("ipv6 must be 16 bytes"));
                 }
                 let ip = Ipv6Addr::from(to_array(&buff[0..16]));
                 context.advance(16);
@@ -179,29 +1205,184 @@ impl ResourceRecord {
             FlagRecordType::SOA => {
                 let soa = SOARecord::parse(context)?;
                 if context.current_idx() != max_index {
-                    return Err("sequence in record exceed specified length");
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
                 }
                 ResourceData::SOA(soa)
             }
             FlagRecordType::NS => {
                 let seq = LabelSeq::parse(context)?;
                 if context.current_idx() != max_index {
-                    return Err("sequence in record exceed specified length");
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
                 }
                 ResourceData::NS(seq)
             }
             FlagRecordType::CNAME => {
                 let seq = LabelSeq::parse(context)?;
                 if context.current_idx() != max_index {
-                    return Err("sequence in record exceed specified length");
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
                 }
                 ResourceData::CNAME(seq)
             }
+            FlagRecordType::PTR => {
+                let seq = LabelSeq::parse(context)?;
+                if context.current_idx() != max_index {
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
+                }
+                ResourceData::PTR(seq)
+            }
+            FlagRecordType::MX => {
+                let mx = MXRecord::parse(context)?;
+                if context.current_idx() != max_index {
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
+                }
+                ResourceData::MX(mx)
+            }
+            FlagRecordType::SRV => {
+                let srv = SRVRecord::parse(context)?;
+                if context.current_idx() != max_index {
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
+                }
+                ResourceData::SRV(srv)
+            }
+            FlagRecordType::TXT => ResourceData::TXT(parse_character_strings(context, size)?),
+            FlagRecordType::OPT => ResourceData::OPT(EdnsOption::parse_all(context, size)?),
+            FlagRecordType::DS => ResourceData::DS(DSRecord::parse(context, size)?),
+            FlagRecordType::RRSIG => ResourceData::RRSIG(RRSIGRecord::parse(context, size)?),
+            FlagRecordType::NSEC => ResourceData::NSEC(NSECRecord::parse(context, size)?),
+            FlagRecordType::DNSKEY => ResourceData::DNSKEY(DNSKEYRecord::parse(context, size)?),
+            FlagRecordType::NSEC3 => ResourceData::NSEC3(NSEC3Record::parse(context, size)?),
+            FlagRecordType::NSEC3PARAM => {
+                ResourceData::NSEC3PARAM(NSEC3PARAMRecord::parse(context, size)?)
+            }
+            FlagRecordType::TSIG => {
+                let tsig = TSIGRecord::parse(context)?;
+                if context.current_idx() != max_index {
+                    return Err(Error::BadRData(
+                        "sequence in record exceed specified length",
+                    ));
+                }
+                ResourceData::TSIG(tsig)
+            }
             _ => {
-                return Err("cannot parse resource data");
+                let rdata = buff[..size].to_vec();
+                context.advance(size);
+                ResourceData::Unknown {
+                    rtype: record_type.bits(),
+                    rdata,
+                }
             }
         };
 
         Ok(res)
     }
 }
+
+/// Same layout as the output of dig: owner, TTL, class, type and data separated by tabs
+impl fmt::Display for ResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name, self.ttl, self.class_code, self.record_type, self.data
+        )
+    }
+}
+
+/// Read one record such as `www.example.com. 300 IN A 1.2.3.4`.
+/// Relative names are taken as relative to the root
+impl FromStr for ResourceRecord {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let entries = text::entries(s)?;
+        let [entry] = entries.as_slice() else {
+            return Err(Error::BadSyntax("expected exactly one record".to_string()));
+        };
+        if entry.owner_omitted {
+            return Err(Error::BadSyntax("missing owner name".to_string()));
+        }
+        ResourceRecord::from_tokens(&entry.tokens, &LabelSeq::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_tag_of_rfc4034() {
+        // Key of the DS example of RFC 4034 section 5.4
+        let key = DNSKEYRecord {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: BASE64
+                .decode(
+                    "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZ\
+                     DRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9Xzc\
+                     nOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+                )
+                .unwrap(),
+        };
+        assert_eq!(key.key_tag(), 60485);
+    }
+
+    /// Bitmap of `A MX RRSIG NSEC TYPE1234`, from the example of RFC 4034 section 4.3
+    const BITMAP: &[u8] = &[
+        0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20,
+    ];
+
+    fn bitmap_types() -> Vec<FlagRecordType> {
+        vec![
+            FlagRecordType::A,
+            FlagRecordType::MX,
+            FlagRecordType::RRSIG,
+            FlagRecordType::NSEC,
+            FlagRecordType::from_bits_retain(1234),
+        ]
+    }
+
+    #[test]
+    fn type_bitmap_of_rfc4034() {
+        let mut context = SerializeContext::new();
+        // Order and duplicates do not matter
+        let mut types = bitmap_types();
+        types.reverse();
+        types.push(FlagRecordType::A);
+        serialize_type_bitmap(&types, &mut context);
+        assert_eq!(context.to_vec(), BITMAP);
+
+        assert_eq!(parse_type_bitmap(BITMAP).unwrap(), bitmap_types());
+    }
+
+    #[test]
+    fn type_bitmap_round_trip() {
+        let types = parse_type_bitmap(BITMAP).unwrap();
+        let mut context = SerializeContext::new();
+        serialize_type_bitmap(&types, &mut context);
+        assert_eq!(context.to_vec(), BITMAP);
+    }
+
+    #[test]
+    fn type_bitmap_rejects_bad_windows() {
+        // Windows out of order
+        assert!(parse_type_bitmap(&[0x04, 0x01, 0x20, 0x00, 0x01, 0x40]).is_err());
+        // Empty and cut windows
+        assert!(parse_type_bitmap(&[0x00, 0x00]).is_err());
+        assert!(parse_type_bitmap(&[0x00, 0x02, 0x40]).is_err());
+    }
+}
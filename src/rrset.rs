@@ -0,0 +1,132 @@
+//! Records grouped into RRsets, as per RFC 2181 section 5
+//!
+//! Sections of a message are loose lists of records. The records of an RRset share owner,
+//! type and class, and are used as a whole: they are cached, signed and dropped together.
+//! Grouping keeps the order in which RRsets first appear, so that converting back to
+//! records leaves a section as it was, apart from duplicates and differing TTLs
+
+use std::collections::HashMap;
+
+use crate::common::{FlagClassCode, FlagRecordType, LabelSeq, SerializeContext};
+use crate::resourserecord::{ResourceData, ResourceRecord};
+
+#[derive(Debug, Clone)]
+pub struct RRset {
+    pub name: LabelSeq,
+    pub record_type: FlagRecordType,
+    pub class_code: FlagClassCode,
+    pub ttl: u32,
+    pub data: Vec<ResourceData>,
+}
+
+impl RRset {
+    /// RRset of the given record alone
+    pub fn new(record: ResourceRecord) -> RRset {
+        RRset {
+            name: record.name,
+            record_type: record.record_type,
+            class_code: record.class_code,
+            ttl: record.ttl,
+            data: vec![record.data],
+        }
+    }
+
+    /// Group records by owner, type and class, in order of first appearance
+    pub fn from_records<'a>(records: impl IntoIterator<Item = &'a ResourceRecord>) -> Vec<RRset> {
+        let mut rrsets: Vec<RRset> = vec![];
+        let mut index: HashMap<(LabelSeq, u16, u16), usize> = HashMap::new();
+        for record in records {
+            let key = (
+                record.name.clone(),
+                record.record_type.bits(),
+                record.class_code.bits(),
+            );
+            match index.get(&key) {
+                Some(&i) => rrsets[i].push(record.clone()),
+                None => {
+                    index.insert(key, rrsets.len());
+                    rrsets.push(RRset::new(record.clone()));
+                }
+            }
+        }
+        rrsets
+    }
+
+    /// Whether `record` has the owner, type and class of this RRset
+    pub fn includes(&self, record: &ResourceRecord) -> bool {
+        record.name == self.name
+            && record.record_type == self.record_type
+            && record.class_code == self.class_code
+    }
+
+    /// Add a record of this RRset
+    ///
+    /// Duplicate records are kept once, and the TTL of the RRset is the smallest one
+    /// when they differ, as per RFC 2181 section 5.2
+    pub fn push(&mut self, record: ResourceRecord) {
+        self.ttl = self.ttl.min(record.ttl);
+        let rdata = uncompressed_rdata(&record.data);
+        if !self
+            .data
+            .iter()
+            .any(|data| uncompressed_rdata(data) == rdata)
+        {
+            self.data.push(record.data);
+        }
+    }
+
+    pub fn to_records(&self) -> Vec<ResourceRecord> {
+        self.data
+            .iter()
+            .map(|data| ResourceRecord {
+                name: self.name.clone(),
+                record_type: self.record_type.clone(),
+                class_code: self.class_code.clone(),
+                ttl: self.ttl,
+                data: data.clone(),
+            })
+            .collect()
+    }
+
+    /// Same RRset in the canonical form and order of RFC 4034 section 6,
+    /// with names in lowercase and records sorted by their uncompressed rdata
+    pub fn to_canonical(&self) -> RRset {
+        let mut data: Vec<(Vec<u8>, ResourceData)> = self
+            .data
+            .iter()
+            .map(|data| {
+                let data = data.to_canonical();
+                (uncompressed_rdata(&data), data)
+            })
+            .collect();
+        data.sort_by(|(a, _), (b, _)| a.cmp(b));
+        data.dedup_by(|(a, _), (b, _)| a == b);
+
+        RRset {
+            name: self.name.to_lowercase(),
+            data: data.into_iter().map(|(_, data)| data).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Serialize the records one after the other without compression,
+    /// as in the data covered by signatures (RFC 4034 section 3.1.8.1)
+    pub fn serialize_uncompressed(&self, context: &mut SerializeContext) {
+        for data in &self.data {
+            self.name.serialize_uncompressed(context);
+            context.extend_from_slice(&self.record_type.bits().to_be_bytes());
+            context.extend_from_slice(&self.class_code.bits().to_be_bytes());
+            context.extend_from_slice(&self.ttl.to_be_bytes());
+            let rdata = uncompressed_rdata(data);
+            context.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            context.extend_from_slice(&rdata);
+        }
+    }
+}
+
+/// Rdata with names left uncompressed, in their original case
+fn uncompressed_rdata(data: &ResourceData) -> Vec<u8> {
+    let mut context = SerializeContext::uncompressed();
+    data.serialize(&mut context);
+    context.to_vec()
+}
@@ -0,0 +1,189 @@
+//! Zones kept up to date from a primary server, as per RFC 1034 section 4.3.5
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use static_init::dynamic;
+use tokio::sync::{watch, Notify};
+use tokio::time::sleep;
+
+use crate::common::{FlagRecordType, LabelSeq};
+use crate::config::parse_server_addr;
+use crate::error::Error;
+use crate::forwarder;
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::ResourceData;
+use crate::transfer;
+use crate::tsig::TsigKey;
+use crate::zone::{serial_gt, Zone, ZONES};
+
+/// Delay before retrying when the zone was never transferred
+const INITIAL_RETRY: Duration = Duration::from_secs(10);
+
+/// Zone to pull from a primary, written `ZONE=IP` or `ZONE=IP:PORT`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecondaryZone {
+    pub origin: LabelSeq,
+    pub primary: SocketAddr,
+}
+
+impl FromStr for SecondaryZone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (origin, primary) = s
+            .split_once('=')
+            .ok_or(format!("expected ZONE=PRIMARY, got {s}"))?;
+
+        let origin = origin
+            .parse()
+            .map_err(|err| format!("invalid zone name {origin}: {err}"))?;
+        let primary = parse_server_addr(primary)?;
+
+        Ok(SecondaryZone { origin, primary })
+    }
+}
+
+impl fmt::Display for SecondaryZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.origin, self.primary)
+    }
+}
+
+/// Wake-up signal of the task maintaining each secondary zone
+#[dynamic]
+static REFRESH_REQUESTS: Mutex<HashMap<LabelSeq, Arc<Notify>>> = Mutex::new(HashMap::new());
+
+/// Check the primary of a secondary zone now rather than at the next timer
+///
+/// Return false if this is not a secondary zone
+pub fn request_refresh(origin: &LabelSeq) -> bool {
+    match REFRESH_REQUESTS.lock().unwrap().get(origin) {
+        Some(notify) => {
+            notify.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Keep a secondary zone up to date until the server stops
+///
+/// The SOA of the primary is checked every `refresh` seconds, or `retry` seconds after a failure,
+/// or as soon as a refresh is requested.
+/// The zone is no longer served once the primary could not be reached for `expire` seconds.
+/// Messages to the primary are signed with `key`, if any
+pub async fn maintain(
+    secondary: SecondaryZone,
+    key: Option<TsigKey>,
+    mut stoprx: watch::Receiver<bool>,
+) {
+    let mut last_success: Option<Instant> = None;
+    let refresh_requested = Arc::new(Notify::new());
+    REFRESH_REQUESTS
+        .lock()
+        .unwrap()
+        .insert(secondary.origin.clone(), refresh_requested.clone());
+
+    loop {
+        let delay = match refresh(&secondary, key.as_ref()).await {
+            Ok(refresh) => {
+                last_success = Some(Instant::now());
+                refresh
+            }
+            Err(err) => {
+                warn!("Cannot refresh zone {}: {err}", secondary);
+                match (ZONES.get(&secondary.origin), last_success) {
+                    (Some(zone), Some(at)) => {
+                        let soa = zone.soa_data();
+                        if at.elapsed() >= Duration::from_secs(soa.expire as u64) {
+                            warn!("Zone {} expired, stop serving it", secondary.origin);
+                            ZONES.remove(&secondary.origin);
+                            last_success = None;
+                            INITIAL_RETRY
+                        } else {
+                            Duration::from_secs(soa.retry as u64)
+                        }
+                    }
+                    _ => INITIAL_RETRY,
+                }
+            }
+        };
+
+        debug!("Next refresh of zone {} in {delay:?}", secondary.origin);
+        tokio::select! {
+            _ = stoprx.changed() => break,
+            _ = sleep(delay) => {}
+            _ = refresh_requested.notified() => {
+                debug!("Refresh of zone {} requested", secondary.origin);
+            }
+        }
+    }
+
+    REFRESH_REQUESTS.lock().unwrap().remove(&secondary.origin);
+}
+
+/// Transfer the zone if the primary has a newer serial, return the delay before the next check
+async fn refresh(secondary: &SecondaryZone, key: Option<&TsigKey>) -> Result<Duration, Error> {
+    let current = ZONES.get(&secondary.origin);
+    let serial = query_serial(secondary, key).await?;
+
+    if let Some(zone) = &current {
+        if !serial_gt(serial, zone.serial()) {
+            debug!("Zone {} is up to date at serial {serial}", secondary.origin);
+            return Ok(Duration::from_secs(zone.soa_data().refresh as u64));
+        }
+    }
+
+    let records = transfer::request_transfer(
+        secondary.primary,
+        &secondary.origin,
+        current.as_deref(),
+        key,
+    )
+    .await?;
+    let Some(records) = records else {
+        let refresh = current.map(|zone| Duration::from_secs(zone.soa_data().refresh as u64));
+        return Ok(refresh.unwrap_or(INITIAL_RETRY));
+    };
+
+    let zone = Zone::from_records(records)?;
+    if zone.origin != secondary.origin {
+        return Err(Error::BadZone(format!(
+            "primary sent zone {} instead",
+            zone.origin
+        )));
+    }
+
+    info!(
+        "Transferred zone {} at serial {} from {}",
+        zone.origin,
+        zone.serial(),
+        secondary.primary
+    );
+    let refresh = Duration::from_secs(zone.soa_data().refresh as u64);
+    ZONES.insert(zone);
+    Ok(refresh)
+}
+
+async fn query_serial(secondary: &SecondaryZone, key: Option<&TsigKey>) -> Result<u32, Error> {
+    let mut request = Message::new();
+    request.add_question(Question::new(secondary.origin.clone(), FlagRecordType::SOA));
+    let primary = secondary.primary.to_string();
+    let reply = forwarder::send_message_to(request, primary, key).await?;
+
+    reply
+        .resources
+        .iter()
+        .find_map(|r| match &r.data {
+            ResourceData::SOA(soa) if r.name == secondary.origin => Some(soa.serial),
+            _ => None,
+        })
+        .ok_or_else(|| Error::BadZone(format!("primary has no SOA for {}", secondary.origin)))
+}
@@ -0,0 +1,111 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::{debug, error, info};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout;
+
+use crate::config::Config;
+use crate::{handle_request, Protocol};
+
+/// Read one message prefixed with its 2-byte length, as per RFC 1035 section 4.2.2
+///
+/// Return `None` if the peer closed the connection before a new message
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let mut buff = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut buff).await?;
+    Ok(Some(buff))
+}
+
+/// Write one message prefixed with its 2-byte length
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, buff: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(buff.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long for TCP"))?;
+
+    let mut frame = Vec::with_capacity(buff.len() + 2);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(buff);
+    writer.write_all(&frame).await
+}
+
+pub async fn serve(listener: TcpListener, cfg: Config, mut stoprx: watch::Receiver<bool>) {
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            _ = stoprx.changed() => break,
+            res = listener.accept() => match res {
+                Ok(res) => res,
+                Err(err) => {
+                    error!("Cannot accept TCP connection: {err}");
+                    continue;
+                }
+            },
+        };
+
+        debug!("Accepted TCP connection from {peer_addr}");
+        let cfg = cfg.clone();
+        tokio::spawn(async move { handle_connection(stream, peer_addr, cfg).await });
+    }
+    info!("Stopped accepting TCP connections");
+}
+
+/// Serve pipelined queries of one connection
+///
+/// Each query is resolved in its own task, so replies are sent as soon as they are ready,
+/// possibly out of order as allowed by RFC 7766 section 6.2.1.1.
+/// The connection is closed once idle, after all pending replies have been sent
+async fn handle_connection(stream: TcpStream, addr: SocketAddr, cfg: Config) {
+    let (mut reader, mut writer) = stream.into_split();
+    // Replies to one request are queued together, so that a zone transfer is not interleaved
+    let (tx, mut rx) = mpsc::channel::<Vec<Vec<u8>>>(64);
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(buffs) = rx.recv().await {
+            for buff in buffs {
+                if let Err(err) = write_frame(&mut writer, &buff).await {
+                    error!("Cannot send TCP response to {addr}: {err}");
+                    return;
+                }
+                debug!("Sent {} bytes to {addr} over TCP", buff.len());
+            }
+        }
+    });
+
+    let idle_timeout = Duration::from_secs(cfg.tcp_idle_timeout);
+    loop {
+        let buff = match timeout(idle_timeout, read_frame(&mut reader)).await {
+            Ok(Ok(Some(buff))) => buff,
+            Ok(Ok(None)) => break,
+            Ok(Err(err)) => {
+                debug!("Cannot read from {addr}: {err}");
+                break;
+            }
+            Err(_) => {
+                debug!("Close idle TCP connection from {addr}");
+                break;
+            }
+        };
+
+        let tx = tx.clone();
+        let cfg = cfg.clone();
+        tokio::spawn(async move {
+            let res = handle_request(&cfg, buff, addr, Protocol::Tcp).await;
+            if !res.is_empty() {
+                // Writer is gone if the connection failed, nothing left to do
+                let _ = tx.send(res).await;
+            }
+        });
+    }
+
+    drop(tx);
+    let _ = writer_task.await;
+}
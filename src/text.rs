@@ -0,0 +1,470 @@
+//! Helpers for the presentation format of RFC 1035 section 5.1
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::common::{FlagClassCode, FlagRecordType};
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// Text of the token, with escapes left as is and without surrounding quotes
+    pub text: String,
+    pub quoted: bool,
+}
+
+/// Tokens making up one entry, which may span several lines inside parentheses
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub tokens: Vec<Token>,
+    /// Entry starts with a blank, meaning the owner is the previous one
+    pub owner_omitted: bool,
+    /// Line the entry starts on, counting from 1
+    pub line: usize,
+}
+
+/// Split text into entries of whitespace separated tokens
+///
+/// Comments, quotes, escapes and parentheses are handled as per RFC 1035 section 5.1.
+/// Empty lines are skipped
+pub fn entries(s: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = vec![];
+    let mut tokens = vec![];
+    let mut owner_omitted = false;
+    let mut start_line = 1;
+    let mut line = 1;
+    let mut at_line_start = true;
+    let mut in_parens = false;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if at_line_start && !in_parens {
+            owner_omitted = c == ' ' || c == '\t';
+            start_line = line;
+        }
+        at_line_start = false;
+
+        match c {
+            '\n' => {
+                line += 1;
+                at_line_start = true;
+                if !in_parens && !tokens.is_empty() {
+                    entries.push(Entry {
+                        tokens: std::mem::take(&mut tokens),
+                        owner_omitted,
+                        line: start_line,
+                    });
+                }
+            }
+            ' ' | '\t' | '\r' => {}
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' if in_parens => return Err(syntax(line, "nested parentheses")),
+            '(' => in_parens = true,
+            ')' if !in_parens => return Err(syntax(line, "unbalanced parentheses")),
+            ')' => in_parens = false,
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(syntax(line, "unterminated quoted string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            if let Some(c) = chars.next() {
+                                text.push(c);
+                            }
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                    }
+                }
+                tokens.push(Token { text, quoted: true });
+            }
+            _ => {
+                let mut text = String::from(c);
+                if c == '\\' {
+                    if let Some(c) = chars.next() {
+                        text.push(c);
+                    }
+                }
+                while let Some(c) = chars.next_if(|c| !" \t\r\n;()\"".contains(*c)) {
+                    text.push(c);
+                    if c == '\\' {
+                        if let Some(c) = chars.next() {
+                            text.push(c);
+                        }
+                    }
+                }
+                tokens.push(Token {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+
+    if in_parens {
+        return Err(syntax(line, "unbalanced parentheses"));
+    }
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            tokens,
+            owner_omitted,
+            line: start_line,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn syntax(line: usize, reason: &str) -> Error {
+    Error::BadSyntax(format!("line {line}: {reason}"))
+}
+
+/// Replace `\X` and `\DDD` escapes with the byte they stand for
+pub fn unescape(s: &str) -> Result<Vec<u8>, Error> {
+    unescape_bytes(s.as_bytes())
+}
+
+pub fn unescape_bytes(s: &[u8]) -> Result<Vec<u8>, Error> {
+    let bad =
+        |reason: &str| Error::BadSyntax(format!("{reason} in {:?}", String::from_utf8_lossy(s)));
+    let mut bytes = vec![];
+    let mut iter = s.iter().copied();
+
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        match iter.next() {
+            Some(d) if d.is_ascii_digit() => {
+                let digits = [Some(d), iter.next(), iter.next()];
+                let mut value = 0u32;
+                for digit in digits {
+                    match digit {
+                        Some(d) if d.is_ascii_digit() => value = value * 10 + (d - b'0') as u32,
+                        _ => return Err(bad("bad escape")),
+                    }
+                }
+                let byte = u8::try_from(value).map_err(|_| bad("escape out of range"))?;
+                bytes.push(byte);
+            }
+            Some(c) => bytes.push(c),
+            None => return Err(bad("dangling escape")),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Parse a TTL, either as seconds or with units such as `1h30m`, as BIND does
+pub fn parse_ttl(s: &str) -> Result<u32, Error> {
+    let bad = || Error::BadSyntax(format!("invalid TTL {s:?}"));
+
+    if let Ok(ttl) = s.parse::<u32>() {
+        return Ok(ttl);
+    }
+
+    let mut total: u32 = 0;
+    let mut value: Option<u32> = None;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            value = Some(
+                value
+                    .unwrap_or(0)
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add(d))
+                    .ok_or_else(bad)?,
+            );
+            continue;
+        }
+
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(bad()),
+        };
+        let amount = value.take().ok_or_else(bad)?;
+        total = amount
+            .checked_mul(unit)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(bad)?;
+    }
+
+    if value.is_some() || s.is_empty() {
+        return Err(bad());
+    }
+    Ok(total)
+}
+
+/// Parse a decimal field of resource data
+pub fn parse_number<T: std::str::FromStr>(token: Option<&Token>, what: &str) -> Result<T, Error> {
+    let token = token.ok_or_else(|| Error::BadSyntax(format!("missing {what}")))?;
+    token
+        .text
+        .parse()
+        .map_err(|_| Error::BadSyntax(format!("invalid {what} {:?}", token.text)))
+}
+
+/// Write bytes as uppercase hexadecimal digits
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Read hexadecimal digits, in either case
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let bad = || Error::BadSyntax(format!("invalid hex data {s:?}"));
+    if !s.len().is_multiple_of(2) {
+        return Err(bad());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2).ok_or_else(bad)?, 16).map_err(|_| bad()))
+        .collect()
+}
+
+/// Read base64 data, which may have been split into several tokens
+pub fn parse_base64<'a>(
+    tokens: impl Iterator<Item = &'a Token>,
+    what: &str,
+) -> Result<Vec<u8>, Error> {
+    let joined: String = tokens.map(|t| t.text.as_str()).collect();
+    if joined.is_empty() {
+        return Err(Error::BadSyntax(format!("missing {what}")));
+    }
+    BASE64
+        .decode(&joined)
+        .map_err(|_| Error::BadSyntax(format!("invalid base64 {what} {joined:?}")))
+}
+
+const BASE32HEX_DIGITS: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+/// Write bytes in the "Extended Hex" base32 alphabet of RFC 4648 section 7, without padding,
+/// as NSEC3 hashes are (RFC 5155 section 3.3)
+pub fn to_base32hex(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32HEX_DIGITS[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(BASE32HEX_DIGITS[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    s
+}
+
+/// Read base32hex digits, in either case and without padding
+pub fn parse_base32hex(s: &str) -> Result<Vec<u8>, Error> {
+    let bad = || Error::BadSyntax(format!("invalid base32hex data {s:?}"));
+    let mut bytes = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            d @ b'0'..=b'9' => d - b'0',
+            d @ b'A'..=b'V' => d - b'A' + 10,
+            _ => return Err(bad()),
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    // Left over bits are padding, which must be zero and shorter than a digit,
+    // else the digits cannot come from whole bytes
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return Err(bad());
+    }
+    Ok(bytes)
+}
+
+/// Read a time of RRSIG records, either as `YYYYMMDDHHmmSS` in UTC or as seconds since
+/// the epoch, as per RFC 4034 section 3.2
+pub fn parse_timestamp(s: &str) -> Result<u32, Error> {
+    let bad = || Error::BadSyntax(format!("invalid timestamp {s:?}"));
+    if s.len() != 14 {
+        return s.parse().map_err(|_| bad());
+    }
+    if !s.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(bad());
+    }
+
+    let field = |range: std::ops::Range<usize>| s[range].parse::<i64>().unwrap_or_default();
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(bad());
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(seconds).map_err(|_| bad())
+}
+
+/// Write a time of RRSIG records as `YYYYMMDDHHmmSS` in UTC
+pub fn format_timestamp(seconds: u32) -> String {
+    let seconds = seconds as i64;
+    let (year, month, day) = civil_from_days(seconds / 86400);
+    let time = seconds % 86400;
+    format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date of the proleptic Gregorian calendar of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Fields between the owner and the data of a record
+#[derive(Debug, Clone)]
+pub struct RecordHead {
+    pub ttl: Option<u32>,
+    pub class_code: Option<FlagClassCode>,
+    pub record_type: FlagRecordType,
+}
+
+impl RecordHead {
+    /// Read the optional TTL and class, in any order, then the type.
+    /// Return the head along with the remaining tokens
+    pub fn parse(tokens: &[Token]) -> Result<(RecordHead, &[Token]), Error> {
+        let mut ttl = None;
+        let mut class_code = None;
+
+        for (i, token) in tokens.iter().enumerate() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text)?);
+            } else if let (None, Ok(class)) = (&class_code, token.text.parse::<FlagClassCode>()) {
+                class_code = Some(class);
+            } else {
+                let head = RecordHead {
+                    ttl,
+                    class_code,
+                    record_type: token.text.parse()?,
+                };
+                return Ok((head, &tokens[i + 1..]));
+            }
+        }
+
+        Err(Error::BadSyntax("missing record type".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test vectors of RFC 4648 section 10, without the padding
+    const BASE32HEX_VECTORS: &[(&str, &str)] = &[
+        ("", ""),
+        ("f", "CO"),
+        ("fo", "CPNG"),
+        ("foo", "CPNMU"),
+        ("foob", "CPNMUOG"),
+        ("fooba", "CPNMUOJ1"),
+        ("foobar", "CPNMUOJ1E8"),
+    ];
+
+    #[test]
+    fn base32hex_known_answers() {
+        for (bytes, digits) in BASE32HEX_VECTORS {
+            assert_eq!(to_base32hex(bytes.as_bytes()), *digits);
+            assert_eq!(parse_base32hex(digits).unwrap(), bytes.as_bytes());
+            assert_eq!(
+                parse_base32hex(&digits.to_lowercase()).unwrap(),
+                bytes.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn base32hex_rejects_bad_digits() {
+        assert!(parse_base32hex("CPNW").is_err());
+        // Left over bits of "f" must be zero
+        assert!(parse_base32hex("CP").is_err());
+        assert!(parse_base32hex("CPNMUOJ1E9").is_err());
+    }
+
+    #[test]
+    fn base32hex_rejects_partial_bytes() {
+        // Lengths of 1, 3 and 6 modulo 8 digits leave a whole digit of padding
+        for digits in ["C", "CPN", "CPNMUO", "CPNMUOJ1C", "CPNMUOJ1E8C"] {
+            assert!(parse_base32hex(digits).is_err(), "{digits}");
+        }
+        assert!(parse_base32hex("CPNMUOJ1E8").is_ok());
+    }
+
+    #[test]
+    fn timestamps_of_rfc4034() {
+        // Expiration and inception of the example of RFC 4034 section 3.3
+        assert_eq!(parse_timestamp("20030322173103").unwrap(), 1048354263);
+        assert_eq!(parse_timestamp("20030220173103").unwrap(), 1045762263);
+        assert_eq!(parse_timestamp("1048354263").unwrap(), 1048354263);
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let times = [
+            ("19700101000000", 0),
+            ("20240229120000", 1709208000),
+            ("20240301000000", 1709251200),
+            ("21000301000000", 4107542400),
+            ("21060207062815", u32::MAX),
+        ];
+        for (text, seconds) in times {
+            assert_eq!(parse_timestamp(text).unwrap(), seconds);
+            assert_eq!(format_timestamp(seconds), text);
+        }
+    }
+
+    #[test]
+    fn timestamps_out_of_range() {
+        assert!(parse_timestamp("20031322173103").is_err());
+        assert!(parse_timestamp("21060207062816").is_err());
+        assert!(parse_timestamp("2003032217310x").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Zone transfers, as per RFC 5936 for AXFR and RFC 1995 for IXFR
+
+use std::net::SocketAddr;
+
+use log::{debug, info};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::acl;
+use crate::common::{FlagAA, FlagOpcode, FlagRCode, FlagRecordType, LabelSeq, SerializeContext};
+use crate::config::Config;
+use crate::error::Error;
+use crate::forwarder::UPSTREAM_TIMEOUT;
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::tcp;
+use crate::tsig::{Signer, TsigKey};
+use crate::zone::{serial_gt, Zone, ZONES};
+use crate::Protocol;
+
+/// Size of each message of an outgoing transfer, leaving room for the OPT record
+pub const MAX_TRANSFER_MESSAGE_SIZE: usize = 16384;
+
+pub fn is_transfer(request: &Message) -> bool {
+    request.questions.first().is_some_and(|q| {
+        q.record_type == FlagRecordType::AXFR.bits() || q.record_type == FlagRecordType::IXFR.bits()
+    })
+}
+
+/// Answer an AXFR or IXFR request with the whole zone, SOA first and last,
+/// spread over as many messages as needed
+///
+/// Transfers are only served to the clients in `allow_transfer`.
+/// AXFR is only served over TCP.
+/// The journal of a zone keeps the changes made by updates, but is not used to answer IXFR,
+/// which gets the whole zone as allowed by RFC 1995 section 4,
+/// or only the SOA if the client is up to date or asked over UDP
+pub fn answer_transfer(
+    cfg: &Config,
+    request: &Message,
+    addr: SocketAddr,
+    protocol: Protocol,
+) -> Vec<Message> {
+    let mut reply = Message::reply_to(request);
+
+    let is_ixfr = request.questions[0].record_type == FlagRecordType::IXFR.bits();
+    let rcode = if protocol != Protocol::Tcp && !is_ixfr {
+        debug!("Refuse transfer over UDP from {addr}");
+        Some(FlagRCode::NOTIMP)
+    } else if cfg.allow_transfer.is_empty() || !acl::is_allowed(&cfg.allow_transfer, &addr.ip()) {
+        debug!("Refuse transfer to {addr}");
+        Some(FlagRCode::REFUSED)
+    } else if request.header.get_opcode().ok() != Some(FlagOpcode::QUERY) {
+        Some(FlagRCode::NOTIMP)
+    } else if request.questions.len() != 1 {
+        Some(FlagRCode::FORMERR)
+    } else {
+        None
+    };
+    if let Some(rcode) = rcode {
+        reply.header.set_rcode(rcode);
+        return vec![reply];
+    }
+
+    let question = &request.questions[0];
+    let Some(zone) = ZONES.get(&question.name) else {
+        debug!(
+            "Refuse transfer of {} to {addr}: not authoritative",
+            question.name
+        );
+        reply.header.set_rcode(FlagRCode::NOTAUTH);
+        return vec![reply];
+    };
+
+    reply.header.set_aa(FlagAA::TRUE);
+    let soa = zone.soa().clone();
+
+    if is_ixfr {
+        let client_serial = request.auth_resources.iter().find_map(|r| match &r.data {
+            ResourceData::SOA(soa) => Some(soa.serial),
+            _ => None,
+        });
+        let up_to_date = client_serial.is_some_and(|serial| !serial_gt(zone.serial(), serial));
+        if up_to_date || protocol != Protocol::Tcp {
+            debug!("Answer IXFR of {} from {addr} with SOA only", zone.origin);
+            reply.add_resource(soa);
+            return vec![reply];
+        }
+    }
+
+    info!("Transfer zone {} to {addr}", zone.origin);
+    reply.add_resource(soa.clone());
+    for record in zone.records() {
+        if record.record_type != FlagRecordType::SOA {
+            reply.add_resource(record.clone());
+        }
+    }
+    reply.add_resource(soa);
+
+    reply.split_answers(MAX_TRANSFER_MESSAGE_SIZE)
+}
+
+/// Pull a zone from `primary`, with IXFR if there is a `current` version, else with AXFR.
+/// The request is signed with `key`, if any, and so must be the replies
+///
+/// Return the records of the new version, or `None` if `current` is up to date
+pub async fn request_transfer(
+    primary: SocketAddr,
+    origin: &LabelSeq,
+    current: Option<&Zone>,
+    key: Option<&TsigKey>,
+) -> Result<Option<Vec<ResourceRecord>>, Error> {
+    let mut request = Message::new();
+    match current {
+        Some(zone) => {
+            request.add_question(Question::new(origin.clone(), FlagRecordType::IXFR));
+            request.add_auth_resource(zone.soa().clone());
+        }
+        None => {
+            request.add_question(Question::new(origin.clone(), FlagRecordType::AXFR));
+        }
+    }
+
+    let mut signer = key.map(|key| Signer::new(key.clone()));
+    let buff = match &mut signer {
+        Some(signer) => signer.sign(request.serialize()),
+        None => request.serialize(),
+    };
+    let mut verifier = signer.map(|signer| signer.verifier());
+
+    let mut stream = timeout(UPSTREAM_TIMEOUT, TcpStream::connect(primary)).await??;
+    tcp::write_frame(&mut stream, &buff).await?;
+
+    let mut records: Vec<ResourceRecord> = vec![];
+    loop {
+        let buff = timeout(UPSTREAM_TIMEOUT, tcp::read_frame(&mut stream))
+            .await??
+            .ok_or(Error::Truncated("zone transfer"))?;
+        let reply = Message::parse(buff.clone())?;
+        if let Some(verifier) = &mut verifier {
+            verifier.verify(&buff, &reply)?;
+        }
+        if reply.header.id != request.header.id {
+            return Err(Error::BadZone("transfer reply has wrong ID".to_string()));
+        }
+        let rcode = reply.header.get_rcode()?;
+        if rcode != FlagRCode::NOERROR {
+            return Err(Error::BadZone(format!("primary refused transfer: {rcode}")));
+        }
+
+        let is_first = records.is_empty();
+        records.extend(reply.resources);
+        let Some(serial) = records.first().and_then(soa_serial) else {
+            return Err(Error::BadZone(
+                "transfer does not start with SOA".to_string(),
+            ));
+        };
+
+        // A lone SOA in the first message means the current version is up to date
+        if is_first && records.len() == 1 {
+            if current.is_some_and(|zone| !serial_gt(serial, zone.serial())) {
+                return Ok(None);
+            }
+            continue;
+        }
+        if is_complete(&records, serial) {
+            break;
+        }
+    }
+    if let Some(verifier) = &verifier {
+        verifier.finish()?;
+    }
+
+    let is_incremental = records.len() > 2 && soa_serial(&records[1]).is_some();
+    if !is_incremental {
+        // AXFR style, the last record is the SOA again
+        records.pop();
+        return Ok(Some(records));
+    }
+
+    let current = current.ok_or(Error::BadZone(
+        "unexpected incremental transfer".to_string(),
+    ))?;
+    apply_differences(current, &records[1..records.len() - 1]).map(Some)
+}
+
+fn soa_serial(record: &ResourceRecord) -> Option<u32> {
+    match &record.data {
+        ResourceData::SOA(soa) => Some(soa.serial),
+        _ => None,
+    }
+}
+
+/// Whether the transfer ends with the SOA of the new version
+///
+/// Both AXFR and IXFR have an odd number of SOA after the first one once complete:
+/// IXFR has an old and a new SOA for each set of differences, then the final one
+fn is_complete(records: &[ResourceRecord], serial: u32) -> bool {
+    let ends_with_soa =
+        records.len() > 1 && soa_serial(&records[records.len() - 1]) == Some(serial);
+    let n_soa = records[1..]
+        .iter()
+        .filter(|r| soa_serial(r).is_some())
+        .count();
+    ends_with_soa && n_soa % 2 == 1
+}
+
+/// Apply sets of differences to the current version of the zone
+///
+/// Each set starts with the old SOA followed by deleted records,
+/// then the new SOA followed by added records, as per RFC 1995 section 4
+pub(crate) fn apply_differences(
+    current: &Zone,
+    differences: &[ResourceRecord],
+) -> Result<Vec<ResourceRecord>, Error> {
+    let mut zone: Vec<ResourceRecord> = current.records().cloned().collect();
+    let mut deleting = false;
+
+    for record in differences {
+        if record.record_type == FlagRecordType::SOA {
+            deleting = !deleting;
+        }
+
+        if deleting {
+            let idx = zone
+                .iter()
+                .position(|r| same_record(r, record))
+                .ok_or_else(|| Error::BadZone(format!("cannot delete missing record {record}")))?;
+            zone.swap_remove(idx);
+        } else {
+            zone.push(record.clone());
+        }
+    }
+
+    Ok(zone)
+}
+
+/// Whether both records have the same owner, type, class and data, whatever the TTL
+pub(crate) fn same_record(a: &ResourceRecord, b: &ResourceRecord) -> bool {
+    let rdata = |r: &ResourceRecord| {
+        let mut context = SerializeContext::new();
+        r.data.serialize(&mut context);
+        context.to_vec()
+    };
+    a.name == b.name
+        && a.record_type == b.record_type
+        && a.class_code == b.class_code
+        && rdata(a) == rdata(b)
+}
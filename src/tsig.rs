@@ -0,0 +1,644 @@
+//! Transaction signatures, as per RFC 8945
+//!
+//! A TSIG record is added last to each signed message. Its MAC covers the message without it,
+//! and is chained from the request to the replies and from one message of a stream to the next
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::debug;
+use ring::hmac;
+
+use crate::common::{
+    FlagClassCode, FlagRCode, FlagRecordType, LabelSeq, ParseContext, SerializeContext,
+};
+use crate::error::Error;
+use crate::header::Header;
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{ResourceData, ResourceRecord, TSIGRecord};
+
+/// Allowed difference between the clocks of both ends, as recommended by RFC 8945 section 10
+pub const DEFAULT_FUDGE: u16 = 300;
+
+// TSIG errors, as per RFC 8945 section 3
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+
+/// Unsigned messages allowed in a row within a signed stream, as per RFC 8945 section 5.3.1
+const MAX_UNSIGNED_MESSAGES: usize = 99;
+
+/// Mnemonic of a TSIG error, which extends the RCODEs of the header
+pub fn error_name(error: u16) -> String {
+    match error {
+        BADSIG => "BADSIG".to_string(),
+        BADKEY => "BADKEY".to_string(),
+        BADTIME => "BADTIME".to_string(),
+        _ => FlagRCode::from_bits_retain(error).to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl Algorithm {
+    fn hmac(&self) -> hmac::Algorithm {
+        match self {
+            Algorithm::HmacSha256 => hmac::HMAC_SHA256,
+            Algorithm::HmacSha512 => hmac::HMAC_SHA512,
+        }
+    }
+
+    fn mac_len(&self) -> usize {
+        self.hmac().digest_algorithm().output_len()
+    }
+
+    pub fn name(&self) -> LabelSeq {
+        LabelSeq::from_string(&self.to_string())
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::HmacSha256 => write!(f, "hmac-sha256"),
+            Algorithm::HmacSha512 => write!(f, "hmac-sha512"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => Err(format!("unsupported TSIG algorithm {s}")),
+        }
+    }
+}
+
+/// Shared secret, written `[ALGORITHM:]NAME:SECRET` with the secret in base64
+#[derive(Debug, Clone)]
+pub struct TsigKey {
+    pub name: LabelSeq,
+    pub algorithm: Algorithm,
+    key: hmac::Key,
+}
+
+impl FromStr for TsigKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let (algorithm, name, secret) = match parts[..] {
+            [name, secret] => (Algorithm::HmacSha256, name, secret),
+            [algorithm, name, secret] => (algorithm.parse()?, name, secret),
+            _ => return Err(format!("expected [ALGORITHM:]NAME:SECRET, got {s}")),
+        };
+
+        let name = name
+            .parse()
+            .map_err(|err| format!("invalid key name {name}: {err}"))?;
+        let secret = BASE64
+            .decode(secret)
+            .map_err(|err| format!("invalid key secret: {err}"))?;
+        Ok(TsigKey {
+            name,
+            algorithm,
+            key: hmac::Key::new(algorithm.hmac(), &secret),
+        })
+    }
+}
+
+/// Key to sign the messages sent to a server with, written `IP=KEY`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerKey {
+    pub ip: IpAddr,
+    pub key_name: LabelSeq,
+}
+
+impl FromStr for ServerKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, key_name) = s
+            .split_once('=')
+            .ok_or(format!("expected IP=KEY, got {s}"))?;
+        let ip = ip.parse().map_err(|_| format!("invalid IP {ip}"))?;
+        let key_name = key_name
+            .parse()
+            .map_err(|err| format!("invalid key name {key_name}: {err}"))?;
+        Ok(ServerKey { ip, key_name })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Data covered by the MAC, as per RFC 8945 section 4.3
+///
+/// The first message of a transaction covers all TSIG variables,
+/// the next messages of a stream only cover the timers
+fn signed_data(
+    key: &TsigKey,
+    prior_mac: Option<&[u8]>,
+    message: &[u8],
+    tsig: &TSIGRecord,
+    timers_only: bool,
+) -> Vec<u8> {
+    let mut context = SerializeContext::new();
+    if let Some(mac) = prior_mac {
+        context.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        context.extend_from_slice(mac);
+    }
+    context.extend_from_slice(message);
+
+    if !timers_only {
+        key.name.to_lowercase().serialize_uncompressed(&mut context);
+        context.extend_from_slice(&FlagClassCode::ANY.bits().to_be_bytes());
+        context.extend_from_slice(&0u32.to_be_bytes());
+        tsig.algorithm
+            .to_lowercase()
+            .serialize_uncompressed(&mut context);
+    }
+    context.extend_from_slice(&tsig.time_signed.to_be_bytes()[2..]);
+    context.extend_from_slice(&tsig.fudge.to_be_bytes());
+    if !timers_only {
+        context.extend_from_slice(&tsig.error.to_be_bytes());
+        context.extend_from_slice(&(tsig.other.len() as u16).to_be_bytes());
+        context.extend_from_slice(&tsig.other);
+    }
+    context.to_vec()
+}
+
+/// TSIG record of a message, which must be the last additional record
+fn find_tsig(msg: &Message) -> Result<Option<(&LabelSeq, &TSIGRecord)>, Error> {
+    let mut records = msg.addi_resources.iter();
+    let last = records.next_back();
+    if records.any(|r| r.record_type == FlagRecordType::TSIG) {
+        return Err(Error::BadRData("TSIG must be the last record"));
+    }
+    Ok(last.and_then(|r| match &r.data {
+        ResourceData::TSIG(tsig) => Some((&r.name, tsig)),
+        _ => None,
+    }))
+}
+
+/// Message as it was before its TSIG record was added, with its original ID
+fn unsigned_message(buff: &[u8], original_id: u16) -> Result<Vec<u8>, Error> {
+    let header = Header::peek(buff).ok_or(Error::Truncated("header"))?;
+    let mut context = ParseContext::new(buff.to_vec());
+    context.advance(Header::SIZE);
+    for _ in 0..header.n_question {
+        Question::parse(&mut context)?;
+    }
+    let n_record =
+        header.n_answer as usize + header.n_auth_res as usize + header.n_addi_res as usize;
+    for _ in 0..n_record - 1 {
+        ResourceRecord::parse(&mut context)?;
+    }
+
+    let mut message = buff[..context.current_idx()].to_vec();
+    message[0..2].copy_from_slice(&original_id.to_be_bytes());
+    message[10..12].copy_from_slice(&(header.n_addi_res - 1).to_be_bytes());
+    Ok(message)
+}
+
+/// Signs the messages sent in one direction of a transaction
+#[derive(Debug)]
+pub struct Signer {
+    key: TsigKey,
+    /// MAC of the request for the first reply, then of the previous message of the stream
+    prior_mac: Option<Vec<u8>>,
+    /// ID of the request, in case it was changed on the way
+    original_id: Option<u16>,
+    in_stream: bool,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Signer {
+    /// Signer of a request
+    pub fn new(key: TsigKey) -> Signer {
+        Signer {
+            key,
+            prior_mac: None,
+            original_id: None,
+            in_stream: false,
+            error: 0,
+            other: vec![],
+        }
+    }
+
+    fn reply(key: TsigKey, request: &TSIGRecord) -> Signer {
+        Signer {
+            prior_mac: Some(request.mac.clone()),
+            original_id: Some(request.original_id),
+            ..Signer::new(key)
+        }
+    }
+
+    /// Bytes taken by the TSIG record added to each message
+    pub fn tsig_len(&self) -> usize {
+        // Type, class, TTL, RDLENGTH then the fixed part of the data
+        self.key.name.wire_len()
+            + 10
+            + self.key.algorithm.name().wire_len()
+            + 16
+            + self.key.algorithm.mac_len()
+            + self.other.len()
+    }
+
+    /// Add a TSIG record to the serialized message
+    pub fn sign(&mut self, buff: Vec<u8>) -> Vec<u8> {
+        self.sign_at(buff, now())
+    }
+
+    fn sign_at(&mut self, mut buff: Vec<u8>, time_signed: u64) -> Vec<u8> {
+        let Some(header) = Header::peek(&buff) else {
+            return buff;
+        };
+        let original_id = self.original_id.unwrap_or(header.id);
+        let mut tsig = TSIGRecord {
+            algorithm: self.key.algorithm.name(),
+            time_signed,
+            fudge: DEFAULT_FUDGE,
+            mac: vec![],
+            original_id,
+            error: self.error,
+            other: self.other.clone(),
+        };
+
+        let mut message = buff.clone();
+        message[0..2].copy_from_slice(&original_id.to_be_bytes());
+        let data = signed_data(
+            &self.key,
+            self.prior_mac.as_deref(),
+            &message,
+            &tsig,
+            self.in_stream,
+        );
+        tsig.mac = hmac::sign(&self.key.key, &data).as_ref().to_vec();
+        self.prior_mac = Some(tsig.mac.clone());
+        self.in_stream = true;
+
+        let record = ResourceRecord {
+            name: self.key.name.clone(),
+            record_type: FlagRecordType::TSIG,
+            class_code: FlagClassCode::ANY,
+            ttl: 0,
+            data: ResourceData::TSIG(tsig),
+        };
+        let mut context = SerializeContext::new();
+        record.serialize(&mut context);
+        buff.extend_from_slice(&context.to_vec());
+        buff[10..12].copy_from_slice(&(header.n_addi_res + 1).to_be_bytes());
+        buff
+    }
+
+    /// Verifier of the replies to the request signed last
+    pub fn verifier(&self) -> Verifier {
+        Verifier::new(self.key.clone(), self.prior_mac.clone())
+    }
+}
+
+/// Checks the signatures of the messages received in one direction of a transaction
+#[derive(Debug)]
+pub struct Verifier {
+    key: TsigKey,
+    prior_mac: Option<Vec<u8>>,
+    in_stream: bool,
+    /// Messages of the stream received unsigned since the last signed one
+    unsigned: Vec<u8>,
+    n_unsigned: usize,
+}
+
+impl Verifier {
+    fn new(key: TsigKey, prior_mac: Option<Vec<u8>>) -> Verifier {
+        Verifier {
+            key,
+            prior_mac,
+            in_stream: false,
+            unsigned: vec![],
+            n_unsigned: 0,
+        }
+    }
+
+    /// Check the signature of `msg`, parsed from `buff`
+    ///
+    /// Only the messages of a stream after the first one may be unsigned,
+    /// in which case they are covered by the signature of the next one
+    pub fn verify(&mut self, buff: &[u8], msg: &Message) -> Result<(), Error> {
+        let Some((name, tsig)) = find_tsig(msg)? else {
+            if !self.in_stream || self.n_unsigned >= MAX_UNSIGNED_MESSAGES {
+                return Err(Error::BadTsig(BADSIG));
+            }
+            self.unsigned.extend_from_slice(buff);
+            self.n_unsigned += 1;
+            return Ok(());
+        };
+
+        if name != &self.key.name || tsig.algorithm != self.key.algorithm.name() {
+            return Err(Error::BadTsig(BADKEY));
+        }
+        if tsig.error != 0 {
+            return Err(Error::BadTsig(tsig.error));
+        }
+
+        let mut message = std::mem::take(&mut self.unsigned);
+        message.extend(unsigned_message(buff, tsig.original_id)?);
+        let data = signed_data(
+            &self.key,
+            self.prior_mac.as_deref(),
+            &message,
+            tsig,
+            self.in_stream,
+        );
+        if hmac::verify(&self.key.key, &data, &tsig.mac).is_err() {
+            return Err(Error::BadTsig(BADSIG));
+        }
+        if now().abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+            return Err(Error::BadTsig(BADTIME));
+        }
+
+        self.prior_mac = Some(tsig.mac.clone());
+        self.in_stream = true;
+        self.n_unsigned = 0;
+        Ok(())
+    }
+
+    /// Check that the stream did not end with unsigned messages
+    pub fn finish(&self) -> Result<(), Error> {
+        match self.n_unsigned {
+            0 => Ok(()),
+            _ => Err(Error::BadTsig(BADSIG)),
+        }
+    }
+}
+
+/// Check the signature of a request, if any, as per RFC 8945 section 5.2
+///
+/// Return the signer of the replies to a signed request,
+/// or the serialized error reply if the signature is rejected
+pub fn check_request(
+    keys: &[TsigKey],
+    buff: &[u8],
+    request: &Message,
+) -> Result<Option<Signer>, Vec<u8>> {
+    let (name, tsig) = match find_tsig(request) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(None),
+        Err(_) => return Err(Message::error_reply(&request.header, FlagRCode::FORMERR).serialize()),
+    };
+
+    let key = keys
+        .iter()
+        .find(|k| &k.name == name && k.algorithm.name() == tsig.algorithm);
+    let res = match key {
+        Some(key) => Verifier::new(key.clone(), None).verify(buff, request),
+        None => Err(Error::BadTsig(BADKEY)),
+    };
+
+    let error = match res {
+        Ok(()) => return Ok(key.map(|key| Signer::reply(key.clone(), tsig))),
+        Err(Error::BadTsig(error)) => error,
+        Err(_) => return Err(Message::error_reply(&request.header, FlagRCode::FORMERR).serialize()),
+    };
+    debug!(
+        "Reject request signed with key {name}: {}",
+        error_name(error)
+    );
+
+    let mut reply = Message::reply_to(request);
+    reply.header.set_rcode(FlagRCode::NOTAUTH);
+    match (error, key) {
+        // The MAC was right, so the reply is signed and tells the time of this server
+        (BADTIME, Some(key)) => {
+            let mut signer = Signer::reply(key.clone(), tsig);
+            signer.error = BADTIME;
+            signer.other = now().to_be_bytes()[2..].to_vec();
+            Err(signer.sign(reply.serialize()))
+        }
+        _ => {
+            reply.add_addi_resource(ResourceRecord {
+                name: name.clone(),
+                record_type: FlagRecordType::TSIG,
+                class_code: FlagClassCode::ANY,
+                ttl: 0,
+                data: ResourceData::TSIG(TSIGRecord {
+                    mac: vec![],
+                    error,
+                    other: vec![],
+                    ..tsig.clone()
+                }),
+            });
+            Err(reply.serialize())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Query of `example. SOA` with ID 0x1234
+    const QUERY: &str = "123400000001000000000000076578616d706c650000060001";
+    const TIME_SIGNED: u64 = 1700000000;
+
+    fn key(algorithm: &str) -> TsigKey {
+        format!("{algorithm}:test.key.:MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=")
+            .parse()
+            .unwrap()
+    }
+
+    fn query() -> Vec<u8> {
+        crate::text::parse_hex(QUERY).unwrap()
+    }
+
+    fn tsig_of(buff: &[u8]) -> TSIGRecord {
+        let msg = Message::parse(buff.to_vec()).unwrap();
+        match find_tsig(&msg).unwrap() {
+            Some((_, tsig)) => tsig.clone(),
+            None => panic!("message is not signed"),
+        }
+    }
+
+    #[test]
+    fn mac_known_answers() {
+        // MACs computed apart over the data of RFC 8945 section 4.3.3
+        let macs = [
+            (
+                "hmac-sha256",
+                "7f9ba1690a8ed69050591eb5a281dae3253c33f749949c1d733a63e732c8f813",
+            ),
+            (
+                "hmac-sha512",
+                "a1b6c06687d557374e045176fc197509f39b4606c36b45ce42d3b4e95a048344\
+                 4c7cbbdec2829d0280c1974acf26e156678b780dbe4c2b512e3557107f2bef83",
+            ),
+        ];
+        for (algorithm, mac) in macs {
+            let signed = Signer::new(key(algorithm)).sign_at(query(), TIME_SIGNED);
+            let tsig = tsig_of(&signed);
+            let mac = mac.replace(' ', "").to_uppercase();
+            assert_eq!(crate::text::to_hex(&tsig.mac), mac);
+            assert_eq!(tsig.original_id, 0x1234);
+            assert_eq!(tsig.fudge, DEFAULT_FUDGE);
+            assert_eq!(
+                Signer::new(key(algorithm)).tsig_len(),
+                signed.len() - query().len()
+            );
+        }
+    }
+
+    #[test]
+    fn request_and_reply_round_trip() {
+        let keys = [key("hmac-sha256")];
+        let mut client = Signer::new(keys[0].clone());
+        let buff = client.sign(query());
+        let request = Message::parse(buff.clone()).unwrap();
+
+        let mut server = check_request(&keys, &buff, &request).unwrap().unwrap();
+        let reply = server.sign(Message::reply_to(&request).serialize());
+
+        let mut verifier = client.verifier();
+        let msg = Message::parse(reply.clone()).unwrap();
+        verifier.verify(&reply, &msg).unwrap();
+        verifier.finish().unwrap();
+
+        // The reply is chained to this request only
+        let mut other = Signer::new(keys[0].clone());
+        other.sign_at(query(), TIME_SIGNED);
+        assert!(matches!(
+            other.verifier().verify(&reply, &msg),
+            Err(Error::BadTsig(BADSIG))
+        ));
+    }
+
+    /// Error of the TSIG record of the reply to a rejected request
+    fn rejected(keys: &[TsigKey], buff: Vec<u8>) -> (Message, TSIGRecord) {
+        let request = Message::parse(buff.clone()).unwrap();
+        let Err(reply) = check_request(keys, &buff, &request) else {
+            panic!("request was accepted");
+        };
+        let tsig = tsig_of(&reply);
+        (Message::parse(reply).unwrap(), tsig)
+    }
+
+    #[test]
+    fn bad_signature_and_key() {
+        let keys = [key("hmac-sha256")];
+        let mut buff = Signer::new(keys[0].clone()).sign(query());
+        // Question for another type than signed
+        buff[QUERY.len() / 2 - 3] = 2;
+        let (reply, tsig) = rejected(&keys, buff);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOTAUTH);
+        assert_eq!(tsig.error, BADSIG);
+        assert!(tsig.mac.is_empty());
+
+        let buff = Signer::new(key("hmac-sha512")).sign(query());
+        let (_, tsig) = rejected(&keys, buff);
+        assert_eq!(tsig.error, BADKEY);
+
+        let other: TsigKey = "other.key.:MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY="
+            .parse()
+            .unwrap();
+        let (_, tsig) = rejected(&keys, Signer::new(other).sign(query()));
+        assert_eq!(tsig.error, BADKEY);
+    }
+
+    #[test]
+    fn bad_time_reply_is_signed() {
+        let keys = [key("hmac-sha256")];
+        let mut client = Signer::new(keys[0].clone());
+        let buff = client.sign_at(query(), TIME_SIGNED);
+        let (reply, tsig) = rejected(&keys, buff);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOTAUTH);
+        assert_eq!(tsig.error, BADTIME);
+        // Other data holds the time of the server
+        assert_eq!(tsig.other.len(), 6);
+        assert!(!tsig.mac.is_empty());
+
+        let reply = reply.serialize();
+        let msg = Message::parse(reply.clone()).unwrap();
+        assert!(matches!(
+            client.verifier().verify(&reply, &msg),
+            Err(Error::BadTsig(BADTIME))
+        ));
+    }
+
+    /// Message of a stream, with one record so that messages differ
+    fn part(i: u8) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.header.id = 0x1234;
+        let record = format!("example. 300 IN A 192.0.2.{i}");
+        msg.add_resource(record.parse().unwrap());
+        msg.serialize()
+    }
+
+    #[test]
+    fn stream_with_unsigned_messages() {
+        let key = key("hmac-sha256");
+        let mut client = Signer::new(key.clone());
+        let request = client.sign(query());
+        let request = Message::parse(request).unwrap();
+        let (_, tsig) = find_tsig(&request).unwrap().unwrap();
+        let mut server = Signer::reply(key, tsig);
+
+        let mut verifier = client.verifier();
+        let mut receive = |buff: &[u8]| {
+            let msg = Message::parse(buff.to_vec()).unwrap();
+            verifier.verify(buff, &msg)
+        };
+        receive(&server.sign(part(1))).unwrap();
+
+        // Unsigned messages are covered by the MAC of the next signed one, which is
+        // computed over them and that message one after the other
+        let unsigned: Vec<Vec<u8>> = (2..5).map(part).collect();
+        for buff in &unsigned {
+            receive(buff).unwrap();
+        }
+        let mut covered = unsigned.concat();
+        covered.extend(part(5));
+        let signed = server.sign(covered);
+        let tsig = &signed[unsigned.concat().len() + part(5).len()..];
+        let mut last = part(5);
+        last.extend(tsig);
+        // One more additional record
+        last[11] += 1;
+        receive(&last).unwrap();
+
+        // Not more than 99 in a row
+        for i in 0..MAX_UNSIGNED_MESSAGES {
+            receive(&part(i as u8)).unwrap();
+        }
+        assert!(matches!(receive(&part(0)), Err(Error::BadTsig(BADSIG))));
+        assert!(matches!(verifier.finish(), Err(Error::BadTsig(BADSIG))));
+    }
+
+    #[test]
+    fn first_message_must_be_signed() {
+        let mut client = Signer::new(key("hmac-sha256"));
+        client.sign(query());
+        let mut verifier = client.verifier();
+        let msg = Message::parse(part(1)).unwrap();
+        assert!(matches!(
+            verifier.verify(&part(1), &msg),
+            Err(Error::BadTsig(BADSIG))
+        ));
+    }
+}
@@ -0,0 +1,559 @@
+//! Dynamic updates of authoritative zones, as per RFC 2136
+//!
+//! Only zones loaded from a master file can be updated, each change being appended to the
+//! journal of the zone before it is served
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error, info, warn};
+use static_init::dynamic;
+use tokio::task;
+
+use crate::acl;
+use crate::common::{FlagClassCode, FlagRCode, FlagRecordType, LabelSeq};
+use crate::config::Config;
+use crate::error::Error;
+use crate::journal::{self, Journal};
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{ResourceData, ResourceRecord};
+use crate::signer;
+use crate::transfer::same_record;
+use crate::zone::{serial_gt, Zone, ZONES};
+
+/// Journals of the zones that accept updates, keyed by origin
+///
+/// The lock of a journal is held while an update of its zone is applied, so that updates
+/// of a zone do not interleave while those of other zones go on
+#[dynamic]
+static JOURNALS: Mutex<HashMap<LabelSeq, Arc<Mutex<Journal>>>> = Mutex::new(HashMap::new());
+
+/// Accept updates of the zone of `origin`, recording them in `journal`
+pub fn register(origin: LabelSeq, journal: Journal) {
+    JOURNALS
+        .lock()
+        .unwrap()
+        .insert(origin, Arc::new(Mutex::new(journal)));
+}
+
+/// Journal of the zone of `origin`, if it accepts updates
+fn journal_of(origin: &LabelSeq) -> Option<Arc<Mutex<Journal>>> {
+    JOURNALS.lock().unwrap().get(origin).cloned()
+}
+
+/// Answer an UPDATE request, as per RFC 2136 section 3
+///
+/// The zone section is held in the questions, the prerequisite section in the answers
+/// and the update section in the authority records
+pub async fn answer_update(cfg: &Config, request: &Message, addr: SocketAddr) -> Message {
+    let mut reply = Message::reply_to(request);
+
+    if cfg.allow_update.is_empty() || !acl::is_allowed(&cfg.allow_update, &addr.ip()) {
+        debug!("Refuse update from {addr}");
+        reply.header.set_rcode(FlagRCode::REFUSED);
+        return reply;
+    }
+
+    let [zone_question] = &request.questions[..] else {
+        reply.header.set_rcode(FlagRCode::FORMERR);
+        return reply;
+    };
+    if zone_question.record_type != FlagRecordType::SOA.bits() {
+        reply.header.set_rcode(FlagRCode::FORMERR);
+        return reply;
+    }
+
+    let cfg = cfg.clone();
+    let zone_question = zone_question.clone();
+    let prerequisites = request.resources.clone();
+    let updates = request.auth_resources.clone();
+    let rcode = task::spawn_blocking(move || {
+        update_zone(&cfg, &zone_question, &prerequisites, &updates, addr)
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Update from {addr} failed: {err}");
+        FlagRCode::SERVFAIL
+    });
+    reply.header.set_rcode(rcode);
+    reply
+}
+
+/// Apply the updates to the zone of `zone_question` and return the RCODE of the reply
+///
+/// Waiting for other updates and signing the zone again can take long,
+/// so this runs on a blocking thread rather than on the workers serving requests
+fn update_zone(
+    cfg: &Config,
+    zone_question: &Question,
+    prerequisites: &[ResourceRecord],
+    updates: &[ResourceRecord],
+    addr: SocketAddr,
+) -> FlagRCode {
+    let Some(journal) = journal_of(&zone_question.name) else {
+        debug!(
+            "Refuse update of {} from {addr}: not a primary zone",
+            zone_question.name
+        );
+        return FlagRCode::NOTAUTH;
+    };
+    let journal = journal.lock().unwrap();
+    let Some(zone) = ZONES.get(&zone_question.name) else {
+        return FlagRCode::NOTAUTH;
+    };
+    if zone_question.class_code != zone.class_code.bits() {
+        return FlagRCode::NOTAUTH;
+    }
+    // Updates apply to the zone as written, it is signed again afterwards
+    let zone = match signer::unsigned(cfg, &zone) {
+        Ok(zone) => zone,
+        Err(err) => {
+            error!("Cannot update zone {}: {err}", zone.origin);
+            return FlagRCode::SERVFAIL;
+        }
+    };
+
+    let mut records: Vec<ResourceRecord> = zone.records().cloned().collect();
+    let res = check_prerequisites(&zone, &records, prerequisites)
+        .and_then(|_| check_updates(&zone, updates));
+    if let Err(rcode) = res {
+        debug!("Reject update of {} from {addr}: {rcode}", zone.origin);
+        return rcode;
+    }
+
+    for update in updates {
+        apply_update(&zone, &mut records, update);
+    }
+
+    match commit(cfg, &zone, records, &journal) {
+        Ok(true) => info!("Updated zone {} from {addr}", zone.origin),
+        Ok(false) => debug!("Update of {} from {addr} changed nothing", zone.origin),
+        Err(err) => {
+            error!("Cannot update zone {}: {err}", zone.origin);
+            return FlagRCode::SERVFAIL;
+        }
+    }
+    FlagRCode::NOERROR
+}
+
+/// Whether the record names a whole RRset rather than holding data
+fn is_empty(record: &ResourceRecord) -> bool {
+    matches!(&record.data, ResourceData::Unknown { rdata, .. } if rdata.is_empty())
+}
+
+/// Types that only make sense in questions, which cannot be added nor deleted
+fn is_meta_type(record_type: &FlagRecordType) -> bool {
+    [
+        FlagRecordType::OPT,
+        FlagRecordType::IXFR,
+        FlagRecordType::AXFR,
+        FlagRecordType::ANY,
+    ]
+    .contains(record_type)
+}
+
+/// Check the prerequisite section, as per RFC 2136 section 3.2
+fn check_prerequisites(
+    zone: &Zone,
+    records: &[ResourceRecord],
+    prerequisites: &[ResourceRecord],
+) -> Result<(), FlagRCode> {
+    let rrset = |name: &LabelSeq, record_type: &FlagRecordType| -> Vec<&ResourceRecord> {
+        records
+            .iter()
+            .filter(|r| &r.name == name && &r.record_type == record_type)
+            .collect()
+    };
+    let in_use = |name: &LabelSeq| records.iter().any(|r| &r.name == name);
+
+    let mut expected: Vec<&ResourceRecord> = vec![];
+    for prereq in prerequisites {
+        if prereq.ttl != 0 {
+            return Err(FlagRCode::FORMERR);
+        }
+        if !prereq.name.is_subdomain_of(&zone.origin) {
+            return Err(FlagRCode::NOTZONE);
+        }
+
+        let any_type = prereq.record_type == FlagRecordType::ANY;
+        if prereq.class_code == FlagClassCode::ANY {
+            if !is_empty(prereq) {
+                return Err(FlagRCode::FORMERR);
+            }
+            if any_type && !in_use(&prereq.name) {
+                return Err(FlagRCode::NXDOMAIN);
+            }
+            if !any_type && rrset(&prereq.name, &prereq.record_type).is_empty() {
+                return Err(FlagRCode::NXRRSET);
+            }
+        } else if prereq.class_code == FlagClassCode::NONE {
+            if !is_empty(prereq) {
+                return Err(FlagRCode::FORMERR);
+            }
+            if any_type && in_use(&prereq.name) {
+                return Err(FlagRCode::YXDOMAIN);
+            }
+            if !any_type && !rrset(&prereq.name, &prereq.record_type).is_empty() {
+                return Err(FlagRCode::YXRRSET);
+            }
+        } else if prereq.class_code == zone.class_code {
+            expected.push(prereq);
+        } else {
+            return Err(FlagRCode::FORMERR);
+        }
+    }
+
+    // RRsets given with their values must exist with exactly these values
+    for prereq in &expected {
+        let actual = rrset(&prereq.name, &prereq.record_type);
+        let wanted: Vec<&&ResourceRecord> = expected
+            .iter()
+            .filter(|r| r.name == prereq.name && r.record_type == prereq.record_type)
+            .collect();
+        let matches = actual
+            .iter()
+            .all(|a| wanted.iter().any(|w| same_record(a, w)))
+            && wanted
+                .iter()
+                .all(|w| actual.iter().any(|a| same_record(a, w)));
+        if !matches {
+            return Err(FlagRCode::NXRRSET);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the update section before applying anything, as per RFC 2136 section 3.4.1
+fn check_updates(zone: &Zone, updates: &[ResourceRecord]) -> Result<(), FlagRCode> {
+    for update in updates {
+        if !update.name.is_subdomain_of(&zone.origin) {
+            return Err(FlagRCode::NOTZONE);
+        }
+
+        let valid = if update.class_code == zone.class_code {
+            !is_meta_type(&update.record_type)
+        } else if update.class_code == FlagClassCode::ANY {
+            let record_type = &update.record_type;
+            update.ttl == 0
+                && is_empty(update)
+                && (*record_type == FlagRecordType::ANY || !is_meta_type(record_type))
+        } else if update.class_code == FlagClassCode::NONE {
+            update.ttl == 0 && !is_meta_type(&update.record_type) && !is_empty(update)
+        } else {
+            false
+        };
+        if !valid {
+            return Err(FlagRCode::FORMERR);
+        }
+    }
+    Ok(())
+}
+
+/// Apply one record of the update section, as per RFC 2136 section 3.4.2
+///
+/// Changes that cannot be made are silently ignored, as the RFC requires:
+/// CNAME alongside other data, deletion of the SOA or of the NS of the apex
+fn apply_update(zone: &Zone, records: &mut Vec<ResourceRecord>, update: &ResourceRecord) {
+    let at_apex = update.name == zone.origin;
+    let is_apex_kept = |r: &ResourceRecord| {
+        at_apex && (r.record_type == FlagRecordType::SOA || r.record_type == FlagRecordType::NS)
+    };
+
+    if update.class_code == zone.class_code {
+        if update.record_type == FlagRecordType::SOA {
+            let old_serial = zone.serial();
+            match &update.data {
+                ResourceData::SOA(soa) if at_apex && serial_gt(soa.serial, old_serial) => {
+                    records.retain(|r| r.record_type != FlagRecordType::SOA);
+                    records.push(update.clone());
+                }
+                _ => debug!("Ignore update of SOA not newer than serial {old_serial}"),
+            }
+            return;
+        }
+
+        let is_cname = update.record_type == FlagRecordType::CNAME;
+        let conflicts = records
+            .iter()
+            .any(|r| r.name == update.name && (r.record_type == FlagRecordType::CNAME) != is_cname);
+        if conflicts {
+            warn!("Ignore update mixing CNAME and other data: {update}");
+            return;
+        }
+        if is_cname {
+            records.retain(|r| !(r.name == update.name && r.record_type == FlagRecordType::CNAME));
+        }
+
+        match records.iter_mut().find(|r| same_record(r, update)) {
+            Some(record) => record.ttl = update.ttl,
+            None => records.push(update.clone()),
+        }
+    } else if update.class_code == FlagClassCode::ANY {
+        let any_type = update.record_type == FlagRecordType::ANY;
+        records.retain(|r| {
+            r.name != update.name
+                || (!any_type && r.record_type != update.record_type)
+                || is_apex_kept(r)
+        });
+    } else {
+        let deleted = ResourceRecord {
+            class_code: zone.class_code.clone(),
+            ..update.clone()
+        };
+        let n_apex_ns = records
+            .iter()
+            .filter(|r| r.name == zone.origin && r.record_type == FlagRecordType::NS)
+            .count();
+        let keep_last_ns = at_apex && update.record_type == FlagRecordType::NS && n_apex_ns <= 1;
+        if update.record_type != FlagRecordType::SOA && !keep_last_ns {
+            records.retain(|r| !same_record(r, &deleted));
+        }
+    }
+}
+
+/// Serve the updated records, signed if keys are configured for the zone,
+/// and append the change to the journal, with the serial increased unless the update did it already
+///
+/// Return whether anything changed
+fn commit(
+    cfg: &Config,
+    zone: &Zone,
+    mut records: Vec<ResourceRecord>,
+    journal: &Journal,
+) -> Result<bool, Error> {
+    let (deleted, added) = journal::differences(zone, &records);
+    let old_soa = zone.soa().clone();
+    let soa_idx = records
+        .iter()
+        .position(|r| r.record_type == FlagRecordType::SOA)
+        .expect("SOA cannot be deleted by updates");
+    let new_soa = &records[soa_idx];
+    if deleted.is_empty()
+        && added.is_empty()
+        && same_record(new_soa, &old_soa)
+        && new_soa.ttl == old_soa.ttl
+    {
+        return Ok(false);
+    }
+
+    if let ResourceData::SOA(soa) = &mut records[soa_idx].data {
+        if !serial_gt(soa.serial, zone.serial()) {
+            soa.serial = zone.serial().wrapping_add(1);
+        }
+    }
+    let new_soa = records[soa_idx].clone();
+
+    let updated = signer::sign_configured(cfg, Zone::from_records(records)?)?;
+    journal.append(&old_soa, &deleted, &new_soa, &added)?;
+    ZONES.insert(updated);
+    Ok(true)
+}
+
+/// Sign a primary zone anew with the serial increased, so that secondaries transfer
+/// the new signatures too
+///
+/// The journal gets a change of the SOA alone, for the serial to be found again after a restart.
+/// Like updates, this is meant to run on a blocking thread
+pub fn refresh_signatures(cfg: &Config, origin: &LabelSeq) -> Result<(), Error> {
+    let Some(journal) = journal_of(origin) else {
+        return Err(Error::BadZone(format!("{origin} is not a primary zone")));
+    };
+    let journal = journal.lock().unwrap();
+    let Some(zone) = ZONES.get(origin) else {
+        return Err(Error::BadZone(format!("{origin} is not a primary zone")));
+    };
+
+    let zone = signer::unsigned(cfg, &zone)?;
+    let mut records: Vec<ResourceRecord> = zone.records().cloned().collect();
+    for record in &mut records {
+        if let ResourceData::SOA(soa) = &mut record.data {
+            soa.serial = soa.serial.wrapping_add(1);
+        }
+    }
+    commit(cfg, &zone, records, &journal)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::*;
+
+    const SOA: &str = "example. 3600 IN SOA ns.example. admin.example. 1 7200 3600 1209600 300";
+
+    fn record(s: &str) -> ResourceRecord {
+        s.parse().unwrap()
+    }
+
+    fn zone() -> Zone {
+        let records = [
+            SOA,
+            "example. 3600 IN NS ns.example.",
+            "ns.example. 3600 IN A 192.0.2.53",
+            "www.example. 300 IN A 192.0.2.1",
+            "www.example. 300 IN A 192.0.2.2",
+        ];
+        Zone::from_records(records.iter().map(|r| record(r)).collect()).unwrap()
+    }
+
+    /// Record of class ANY or NONE without data, naming a name or an RRset
+    fn empty(name: &str, class_code: FlagClassCode, record_type: FlagRecordType) -> ResourceRecord {
+        ResourceRecord {
+            name: LabelSeq::from_string(name),
+            record_type: record_type.clone(),
+            class_code,
+            ttl: 0,
+            data: ResourceData::Unknown {
+                rtype: record_type.bits(),
+                rdata: vec![],
+            },
+        }
+    }
+
+    fn check(prerequisites: &[ResourceRecord]) -> Result<(), FlagRCode> {
+        let zone = zone();
+        let records: Vec<ResourceRecord> = zone.records().cloned().collect();
+        check_prerequisites(&zone, &records, prerequisites)
+    }
+
+    #[test]
+    fn prerequisites_on_names_and_rrsets() {
+        let (any, none) = (FlagClassCode::ANY, FlagClassCode::NONE);
+        let (a, mx, all) = (FlagRecordType::A, FlagRecordType::MX, FlagRecordType::ANY);
+
+        assert_eq!(
+            check(&[empty("www.example.", any.clone(), all.clone())]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&[empty("new.example.", any.clone(), all.clone())]),
+            Err(FlagRCode::NXDOMAIN)
+        );
+        assert_eq!(
+            check(&[empty("new.example.", none.clone(), all.clone())]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&[empty("www.example.", none.clone(), all)]),
+            Err(FlagRCode::YXDOMAIN)
+        );
+        assert_eq!(
+            check(&[empty("www.example.", any.clone(), a.clone())]),
+            Ok(())
+        );
+        assert_eq!(
+            check(&[empty("www.example.", any, mx.clone())]),
+            Err(FlagRCode::NXRRSET)
+        );
+        assert_eq!(check(&[empty("www.example.", none.clone(), mx)]), Ok(()));
+        assert_eq!(
+            check(&[empty("www.example.", none, a)]),
+            Err(FlagRCode::YXRRSET)
+        );
+    }
+
+    #[test]
+    fn prerequisites_on_rrset_values() {
+        let www = |ip: &str| record(&format!("www.example. 0 IN A {ip}"));
+        assert_eq!(check(&[www("192.0.2.2"), www("192.0.2.1")]), Ok(()));
+        // The RRset must hold exactly these values
+        assert_eq!(check(&[www("192.0.2.1")]), Err(FlagRCode::NXRRSET));
+        assert_eq!(
+            check(&[www("192.0.2.1"), www("192.0.2.2"), www("192.0.2.3")]),
+            Err(FlagRCode::NXRRSET)
+        );
+    }
+
+    #[test]
+    fn malformed_prerequisites() {
+        assert_eq!(
+            check(&[record("www.example. 300 IN A 192.0.2.1")]),
+            Err(FlagRCode::FORMERR)
+        );
+        assert_eq!(
+            check(&[record("www.example.org. 0 IN A 192.0.2.1")]),
+            Err(FlagRCode::NOTZONE)
+        );
+        let mut with_data = record("www.example. 0 IN A 192.0.2.1");
+        with_data.class_code = FlagClassCode::ANY;
+        assert_eq!(check(&[with_data]), Err(FlagRCode::FORMERR));
+    }
+
+    #[test]
+    fn malformed_updates() {
+        let zone = zone();
+        let outside = record("www.example.org. 300 IN A 192.0.2.1");
+        assert_eq!(check_updates(&zone, &[outside]), Err(FlagRCode::NOTZONE));
+        let mut delete = empty("www.example.", FlagClassCode::ANY, FlagRecordType::A);
+        assert_eq!(check_updates(&zone, &[delete.clone()]), Ok(()));
+        delete.ttl = 300;
+        assert_eq!(check_updates(&zone, &[delete]), Err(FlagRCode::FORMERR));
+        let meta = empty("www.example.", FlagClassCode::IN, FlagRecordType::AXFR);
+        assert_eq!(check_updates(&zone, &[meta]), Err(FlagRCode::FORMERR));
+    }
+
+    #[test]
+    fn updates_keep_apex_and_cname_rules() {
+        let zone = zone();
+        let mut records: Vec<ResourceRecord> = zone.records().cloned().collect();
+        let updates = [
+            record("mail.example. 300 IN A 192.0.2.25"),
+            empty("example.", FlagClassCode::ANY, FlagRecordType::ANY),
+            record("www.example. 300 IN CNAME mail.example."),
+            empty("www.example.", FlagClassCode::ANY, FlagRecordType::A),
+        ];
+        for update in &updates {
+            apply_update(&zone, &mut records, update);
+        }
+
+        let count = |name: &str, record_type: FlagRecordType| {
+            let name = LabelSeq::from_string(name);
+            records
+                .iter()
+                .filter(|r| r.name == name && r.record_type == record_type)
+                .count()
+        };
+        assert_eq!(count("mail.example.", FlagRecordType::A), 1);
+        // SOA and NS of the apex are never deleted
+        assert_eq!(count("example.", FlagRecordType::SOA), 1);
+        assert_eq!(count("example.", FlagRecordType::NS), 1);
+        // CNAME is ignored next to the addresses, which are deleted afterwards
+        assert_eq!(count("www.example.", FlagRecordType::CNAME), 0);
+        assert_eq!(count("www.example.", FlagRecordType::A), 0);
+    }
+
+    #[test]
+    fn commit_increases_serial_and_journals() {
+        let cfg = Config::parse_from(["dinosaurust"]);
+        let dir = std::env::temp_dir().join(format!("update-commit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = Journal::for_zone_file(&dir.join("example.zone"));
+
+        let zone = zone();
+        let mut records: Vec<ResourceRecord> = zone.records().cloned().collect();
+        assert!(!commit(&cfg, &zone, records.clone(), &journal).unwrap());
+        assert!(!journal.path.exists());
+
+        apply_update(
+            &zone,
+            &mut records,
+            &record("mail.example. 300 IN A 192.0.2.25"),
+        );
+        assert!(commit(&cfg, &zone, records, &journal).unwrap());
+
+        let served = ZONES.get(&zone.origin).unwrap();
+        assert_eq!(served.serial(), 2);
+        assert!(served
+            .records()
+            .any(|r| r.name == LabelSeq::from_string("mail.example.")));
+        let replayed = journal.replay(self::zone()).unwrap();
+        assert_eq!(replayed.serial(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1121 @@
+//! DNSSEC validation of the answers of the iterative resolver, as per RFC 4035 section 5
+//!
+//! A chain of trust is built from the trust anchor down to the zone of each RRset, one label
+//! at a time: the DS RRset of a child zone, signed in its parent, authenticates the DNSKEY
+//! RRset of the child, while a signed proof that a delegation has no DS makes everything
+//! below it insecure. Keys found along the way are cached for the TTL of their records
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use static_init::dynamic;
+
+use crate::cache::{make_room, MAX_CACHE_TTL};
+use crate::common::{FlagRCode, FlagRecordType, LabelSeq};
+use crate::config::Config;
+use crate::dnssec;
+use crate::error::Error;
+use crate::forwarder::{self, ForwardContext};
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{
+    DNSKEYRecord, DSRecord, NSEC3Record, NSECRecord, RRSIGRecord, ResourceData, ResourceRecord,
+    DNSKEY_FLAG_ZONE, DNSKEY_PROTOCOL, NSEC3_FLAG_OPT_OUT, NSEC3_HASH_SHA1,
+};
+use crate::rrset::RRset;
+use crate::text::{self, RecordHead};
+use crate::zone::serial_gt;
+
+/// Iterations above which NSEC3 records are deemed insecure, as RFC 9276 section 3.2 allows
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// Keys of a zone that is known to be signed, which validation starts from
+#[derive(Debug, Clone)]
+pub struct TrustAnchor {
+    pub owner: LabelSeq,
+    pub ds: Vec<DSRecord>,
+    pub keys: Vec<DNSKEYRecord>,
+}
+
+impl TrustAnchor {
+    /// Read DS or DNSKEY records in presentation format, all with the same owner.
+    /// TTL and class may be left out, as in the root anchors published by IANA
+    pub fn from_file(path: &Path) -> Result<TrustAnchor, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut anchor = TrustAnchor {
+            owner: LabelSeq::new(),
+            ds: vec![],
+            keys: vec![],
+        };
+
+        let entries = text::entries(&content)?;
+        for (i, entry) in entries.iter().enumerate() {
+            let at = |err: Error| Error::BadSyntax(format!("line {}: {err}", entry.line));
+            let owner = LabelSeq::from_text(&entry.tokens[0].text, &LabelSeq::new()).map_err(at)?;
+            let (head, rest) = RecordHead::parse(&entry.tokens[1..]).map_err(at)?;
+            if i > 0 && owner != anchor.owner {
+                return Err(at(Error::BadSyntax(
+                    "trust anchors must have the same owner".to_string(),
+                )));
+            }
+            anchor.owner = owner;
+
+            match ResourceData::from_tokens(head.record_type, rest, &LabelSeq::new()).map_err(at)? {
+                ResourceData::DS(ds) => anchor.ds.push(ds),
+                ResourceData::DNSKEY(key) => anchor.keys.push(key),
+                data => {
+                    return Err(at(Error::BadSyntax(format!(
+                        "trust anchor must be DS or DNSKEY, not {}",
+                        data.record_type()
+                    ))))
+                }
+            }
+        }
+
+        if anchor.ds.is_empty() && anchor.keys.is_empty() {
+            return Err(Error::BadSyntax("no trust anchor found".to_string()));
+        }
+        Ok(anchor)
+    }
+
+    /// Whether `key` is one of the anchors or matches one of them
+    fn trusts(&self, key: &DNSKEYRecord) -> bool {
+        self.keys
+            .iter()
+            .any(|anchor| anchor.algorithm == key.algorithm && anchor.public_key == key.public_key)
+            || matches_ds(&self.owner, key, &self.ds)
+    }
+
+    /// Whether any anchor uses an algorithm this server can validate
+    fn is_usable(&self) -> bool {
+        self.keys
+            .iter()
+            .any(|key| dnssec::is_supported_algorithm(key.algorithm))
+            || self.ds.iter().any(is_usable_ds)
+    }
+}
+
+/// Validate the final reply of the iterative resolver to `question`
+///
+/// Return whether the reply is secure, so that it can be marked as authentic,
+/// or `Error::Bogus` if it must not be trusted
+pub async fn validate(
+    cfg: &Config,
+    anchor: &TrustAnchor,
+    question: &Question,
+    reply: &Message,
+) -> Result<bool, Error> {
+    if !question.name.is_subdomain_of(&anchor.owner) {
+        return Ok(false);
+    }
+    Validator { cfg, anchor }.validate(question, reply).await
+}
+
+/// Keys that data at a name is signed with
+#[derive(Debug, Clone)]
+enum Keys {
+    /// Name belongs to the signed zone `zone`, whose zone keys are `keys`
+    Secure {
+        zone: LabelSeq,
+        keys: Vec<DNSKEYRecord>,
+    },
+    /// Name is below a delegation without DS, or one this server cannot validate
+    Insecure,
+}
+
+struct KeysEntry {
+    keys: Keys,
+    expires_at: Instant,
+}
+
+/// Keys of the names validation went through, shared by all requests,
+/// within the same bound as the cache of answers
+#[dynamic]
+static KEYS: Mutex<HashMap<LabelSeq, KeysEntry>> = Mutex::new(HashMap::new());
+
+fn cached_keys(name: &LabelSeq) -> Option<Keys> {
+    let mut cache = KEYS.lock().unwrap();
+    let entry = cache.get(name)?;
+    if Instant::now() >= entry.expires_at {
+        cache.remove(name);
+        return None;
+    }
+    Some(entry.keys.clone())
+}
+
+fn cache_keys(name: LabelSeq, keys: Keys, ttl: u32) {
+    let now = Instant::now();
+    let expires_at = now + Duration::from_secs(ttl.min(MAX_CACHE_TTL) as u64);
+    let mut cache = KEYS.lock().unwrap();
+    if !cache.contains_key(&name) {
+        make_room(&mut cache, |e| e.expires_at, now);
+    }
+    cache.insert(name, KeysEntry { keys, expires_at });
+}
+
+struct Validator<'a> {
+    cfg: &'a Config,
+    anchor: &'a TrustAnchor,
+}
+
+impl Validator<'_> {
+    async fn validate(&self, question: &Question, reply: &Message) -> Result<bool, Error> {
+        let qtype = FlagRecordType::from_bits_retain(question.record_type);
+        let mut secure = true;
+
+        for rrset in rrsets(&reply.resources) {
+            let Some(rrsig) = self.verify_rrset(&rrset, &reply.resources).await? else {
+                secure = false;
+                continue;
+            };
+            let owner = &rrset.name;
+            if rrsig.labels < dnssec::rrsig_labels(owner) {
+                // Expanded from a wildcard, so the name itself must not exist
+                let Some(denial) = self.denial(&rrsig.signer_name, reply).await? else {
+                    secure = false;
+                    continue;
+                };
+                secure &= denial.prove_expansion(owner, rrsig.labels)?;
+            }
+        }
+
+        // Follow CNAME from the question name to see whether the type was answered
+        let mut target = question.name.clone();
+        for _ in 0..reply.resources.len() {
+            if qtype == FlagRecordType::CNAME {
+                break;
+            }
+            let cname = reply.resources.iter().find_map(|r| match &r.data {
+                ResourceData::CNAME(next) if r.name == target => Some(next.clone()),
+                _ => None,
+            });
+            match cname {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+        let answered = reply
+            .resources
+            .iter()
+            .any(|r| r.name == target && (r.record_type == qtype || qtype == FlagRecordType::ANY));
+        if answered {
+            return Ok(secure);
+        }
+
+        let is_nxdomain = reply.header.get_rcode().ok() == Some(FlagRCode::NXDOMAIN);
+        let soa = rrsets(&reply.auth_resources)
+            .into_iter()
+            .find(|rrset| rrset.record_type == FlagRecordType::SOA);
+        // CNAME are not chased, so the target may be left for the client to ask about,
+        // possibly in another zone: the chain is then as secure as its CNAME RRsets
+        if target != question.name && soa.is_none() && !is_nxdomain {
+            return Ok(secure);
+        }
+        let Some(soa) = soa else {
+            return match self.keys_for(&target).await? {
+                Keys::Insecure => Ok(false),
+                Keys::Secure { .. } => {
+                    Err(bogus(format!("no SOA in negative answer for {target}")))
+                }
+            };
+        };
+        let Some(rrsig) = self.verify_rrset(&soa, &reply.auth_resources).await? else {
+            return Ok(false);
+        };
+        if !target.is_subdomain_of(&rrsig.signer_name) {
+            return Err(bogus(format!(
+                "negative answer for {target} from zone {}",
+                rrsig.signer_name
+            )));
+        }
+        let Some(denial) = self.denial(&rrsig.signer_name, reply).await? else {
+            return Ok(false);
+        };
+
+        let proven = if is_nxdomain {
+            denial.prove_nxdomain(&target)?
+        } else {
+            denial.prove_nodata(&target, &qtype)?
+        };
+        Ok(secure && proven)
+    }
+
+    /// Authenticate an RRset with one of the RRSIG of `section` covering it
+    ///
+    /// Return the signature that matched, or `None` if the RRset is insecure,
+    /// as are those outside the subtree of the anchor
+    async fn verify_rrset(
+        &self,
+        rrset: &RRset,
+        section: &[ResourceRecord],
+    ) -> Result<Option<RRSIGRecord>, Error> {
+        let owner = &rrset.name;
+        if !owner.is_subdomain_of(&self.anchor.owner) {
+            return Ok(None);
+        }
+        let rrsigs = signatures(rrset, section);
+        if rrsigs.is_empty() {
+            return match self.keys_for(owner).await? {
+                Keys::Insecure => Ok(None),
+                Keys::Secure { .. } => Err(bogus(format!(
+                    "no signature for {owner} {}",
+                    rrset.record_type
+                ))),
+            };
+        }
+
+        for rrsig in rrsigs {
+            let signer = &rrsig.signer_name;
+            if !owner.is_subdomain_of(signer) || !signer.is_subdomain_of(&self.anchor.owner) {
+                continue;
+            }
+            match self.keys_for(signer).await? {
+                Keys::Insecure => return Ok(None),
+                Keys::Secure { zone, keys } => {
+                    if zone == *signer && verify_with(rrset, rrsig, &keys) {
+                        return Ok(Some(rrsig.clone()));
+                    }
+                }
+            }
+        }
+        Err(bogus(format!(
+            "no valid signature for {owner} {}",
+            rrset.record_type
+        )))
+    }
+
+    /// Authenticated NSEC and NSEC3 records of the authority section, signed by `zone`
+    ///
+    /// Return `None` if the zone is insecure
+    async fn denial(&self, zone: &LabelSeq, reply: &Message) -> Result<Option<Denial>, Error> {
+        match self.keys_for(zone).await? {
+            Keys::Secure { zone: apex, keys } if apex == *zone => {
+                Denial::from_section(zone, &keys, &reply.auth_resources).map(Some)
+            }
+            Keys::Secure { .. } => Err(bogus(format!("{zone} is not a zone apex"))),
+            Keys::Insecure => Ok(None),
+        }
+    }
+
+    /// Keys that data at `name` must be signed with, following the chain of trust
+    /// from the anchor down to `name`
+    async fn keys_for(&self, name: &LabelSeq) -> Result<Keys, Error> {
+        // Nothing vouches for data outside the subtree of the anchor, as with no anchor at all
+        if !name.is_subdomain_of(&self.anchor.owner) {
+            return Ok(Keys::Insecure);
+        }
+        if let Some(keys) = cached_keys(name) {
+            return Ok(keys);
+        }
+
+        let (keys, ttl) = if *name == self.anchor.owner {
+            if !self.anchor.is_usable() {
+                debug!("Trust anchor {} uses unsupported algorithms", name);
+                (Keys::Insecure, MAX_CACHE_TTL)
+            } else {
+                self.fetch_keys(name, |key| self.anchor.trusts(key)).await?
+            }
+        } else {
+            let parent = name.parent().expect("names below the anchor have a parent");
+            match Box::pin(self.keys_for(&parent)).await? {
+                Keys::Insecure => (Keys::Insecure, MAX_CACHE_TTL),
+                Keys::Secure { zone, keys } => self.child_keys(name, zone, keys).await?,
+            }
+        };
+
+        cache_keys(name.clone(), keys.clone(), ttl);
+        Ok(keys)
+    }
+
+    /// Keys of `name`, knowing the keys of the zone above it
+    ///
+    /// The DS RRset of `name` in that zone tells whether it is a secure delegation,
+    /// an unsigned delegation, or just a name in the same zone
+    async fn child_keys(
+        &self,
+        name: &LabelSeq,
+        zone: LabelSeq,
+        keys: Vec<DNSKEYRecord>,
+    ) -> Result<(Keys, u32), Error> {
+        let reply = self.lookup(name, FlagRecordType::DS).await?;
+
+        if let Some(ds_rrset) = find_rrset(&reply.resources, name, FlagRecordType::DS) {
+            check_signed(&ds_rrset, &reply.resources, &zone, &keys)?;
+            let ttl = ds_rrset.ttl;
+            let ds: Vec<DSRecord> = ds_rrset
+                .data
+                .iter()
+                .filter_map(|data| match data {
+                    ResourceData::DS(ds) if is_usable_ds(ds) => Some(ds.clone()),
+                    _ => None,
+                })
+                .collect();
+            if ds.is_empty() {
+                debug!("DS of {name} use unsupported algorithms, treat as insecure");
+                return Ok((Keys::Insecure, ttl));
+            }
+            let (keys, key_ttl) = self
+                .fetch_keys(name, |key| matches_ds(name, key, &ds))
+                .await?;
+            return Ok((keys, ttl.min(key_ttl)));
+        }
+
+        // Some names have a CNAME in the parent zone instead, they are not zone cuts
+        if let Some(cname) = find_rrset(&reply.resources, name, FlagRecordType::CNAME) {
+            check_signed(&cname, &reply.resources, &zone, &keys)?;
+            return Ok((Keys::Secure { zone, keys }, cname.ttl));
+        }
+
+        let denial = Denial::from_section(&zone, &keys, &reply.auth_resources)?;
+        let ttl = min_ttl(&reply.auth_resources);
+        if denial.proves_unsigned_delegation(name)? {
+            debug!("{name} is an unsigned delegation");
+            Ok((Keys::Insecure, ttl))
+        } else {
+            Ok((Keys::Secure { zone, keys }, ttl))
+        }
+    }
+
+    /// Zone keys of the apex `name`, whose DNSKEY RRset must be signed by a key `trusted`
+    async fn fetch_keys(
+        &self,
+        name: &LabelSeq,
+        trusted: impl Fn(&DNSKEYRecord) -> bool,
+    ) -> Result<(Keys, u32), Error> {
+        let reply = self.lookup(name, FlagRecordType::DNSKEY).await?;
+        let rrset = find_rrset(&reply.resources, name, FlagRecordType::DNSKEY)
+            .ok_or_else(|| bogus(format!("no DNSKEY for {name}")))?;
+        let keys: Vec<DNSKEYRecord> = rrset
+            .data
+            .iter()
+            .filter_map(|data| match data {
+                ResourceData::DNSKEY(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let entry_keys: Vec<DNSKEYRecord> = keys.iter().filter(|k| trusted(k)).cloned().collect();
+        if entry_keys.is_empty() {
+            return Err(bogus(format!("no DNSKEY of {name} matches its DS")));
+        }
+        check_signed(&rrset, &reply.resources, name, &entry_keys)?;
+
+        let zone_keys = keys
+            .into_iter()
+            .filter(|k| k.flags & DNSKEY_FLAG_ZONE != 0 && k.protocol == DNSKEY_PROTOCOL)
+            .collect();
+        let keys = Keys::Secure {
+            zone: name.clone(),
+            keys: zone_keys,
+        };
+        Ok((keys, rrset.ttl))
+    }
+
+    async fn lookup(&self, name: &LabelSeq, record_type: FlagRecordType) -> Result<Message, Error> {
+        let question = Question::new(name.clone(), record_type);
+        let mut context = ForwardContext::new(self.cfg);
+        forwarder::resolve(question, self.cfg, &mut context).await
+    }
+}
+
+fn bogus(reason: String) -> Error {
+    Error::Bogus(reason)
+}
+
+/// RRsets of a section, leaving out signatures
+fn rrsets(records: &[ResourceRecord]) -> Vec<RRset> {
+    RRset::from_records(
+        records.iter().filter(|r| {
+            r.record_type != FlagRecordType::RRSIG && r.record_type != FlagRecordType::OPT
+        }),
+    )
+}
+
+/// RRset of `section` with the given owner and type
+fn find_rrset(
+    section: &[ResourceRecord],
+    name: &LabelSeq,
+    record_type: FlagRecordType,
+) -> Option<RRset> {
+    rrsets(section)
+        .into_iter()
+        .find(|rrset| rrset.name == *name && rrset.record_type == record_type)
+}
+
+/// RRSIG of `section` covering `rrset`
+fn signatures<'a>(rrset: &RRset, section: &'a [ResourceRecord]) -> Vec<&'a RRSIGRecord> {
+    section
+        .iter()
+        .filter(|r| r.name == rrset.name)
+        .filter_map(|r| match &r.data {
+            ResourceData::RRSIG(rrsig) if rrsig.type_covered == rrset.record_type => Some(rrsig),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Check that `rrset` is signed by one of the `keys` of `zone`
+fn check_signed(
+    rrset: &RRset,
+    section: &[ResourceRecord],
+    zone: &LabelSeq,
+    keys: &[DNSKEYRecord],
+) -> Result<(), Error> {
+    let signed = signatures(rrset, section)
+        .into_iter()
+        .any(|rrsig| rrsig.signer_name == *zone && verify_with(rrset, rrsig, keys));
+    if !signed {
+        return Err(bogus(format!(
+            "no valid signature for {} {}",
+            rrset.name, rrset.record_type
+        )));
+    }
+    Ok(())
+}
+
+/// Check a signature of `rrset` with the zone keys it names, as per RFC 4035 section 5.3
+fn verify_with(rrset: &RRset, rrsig: &RRSIGRecord, keys: &[DNSKEYRecord]) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    if serial_gt(rrsig.inception, now) || serial_gt(now, rrsig.expiration) {
+        debug!(
+            "Signature of {} {} is not valid at this time",
+            rrset.name, rrset.record_type
+        );
+        return false;
+    }
+    if rrsig.labels > dnssec::rrsig_labels(&rrset.name) {
+        return false;
+    }
+
+    let data = dnssec::signed_data(rrsig, rrset);
+    keys.iter()
+        .filter(|key| {
+            key.algorithm == rrsig.algorithm
+                && key.flags & DNSKEY_FLAG_ZONE != 0
+                && key.key_tag() == rrsig.key_tag
+        })
+        .any(|key| dnssec::verify_signature(key, &data, &rrsig.signature))
+}
+
+fn is_usable_ds(ds: &DSRecord) -> bool {
+    dnssec::is_supported_algorithm(ds.algorithm) && dnssec::is_supported_digest(ds.digest_type)
+}
+
+/// Whether one of the DS records of `owner` is a digest of `key`
+fn matches_ds(owner: &LabelSeq, key: &DNSKEYRecord, ds: &[DSRecord]) -> bool {
+    ds.iter().any(|ds| {
+        ds.key_tag == key.key_tag()
+            && ds.algorithm == key.algorithm
+            && dnssec::ds_digest(owner, key, ds.digest_type).is_some_and(|d| d == ds.digest)
+    })
+}
+
+fn min_ttl(records: &[ResourceRecord]) -> u32 {
+    records.iter().map(|r| r.ttl).min().unwrap_or(0)
+}
+
+/// Longest name both `a` and `b` are equal to or below
+fn common_ancestor(a: &LabelSeq, b: &LabelSeq) -> LabelSeq {
+    let common = a
+        .labels
+        .iter()
+        .rev()
+        .zip(b.labels.iter().rev())
+        .take_while(|(x, y)| x.eq_ignore_ascii_case(y))
+        .count();
+    LabelSeq {
+        labels: a.labels[a.labels.len() - common..].to_vec(),
+    }
+}
+
+/// Closest encloser of `name`, which the NSEC record at `owner` covers
+fn nsec_closest_encloser(name: &LabelSeq, owner: &LabelSeq, nsec: &NSECRecord) -> LabelSeq {
+    let a = common_ancestor(name, owner);
+    let b = common_ancestor(name, &nsec.next_domain);
+    if a.labels.len() > b.labels.len() {
+        a
+    } else {
+        b
+    }
+}
+
+/// Whether the types of a name make it the parent side of a zone cut
+fn is_delegation(types: &[FlagRecordType]) -> bool {
+    types.contains(&FlagRecordType::NS) && !types.contains(&FlagRecordType::SOA)
+}
+
+/// Whether the types of a name keep its NSEC or NSEC3 record from proving anything
+/// about the names below it, which are in another zone or redirected,
+/// as per RFC 4035 section 5.4 and RFC 6840 section 4.1
+fn hides_descendants(types: &[FlagRecordType]) -> bool {
+    is_delegation(types) || types.contains(&FlagRecordType::DNAME)
+}
+
+/// Authenticated NSEC and NSEC3 records of a zone, proving that names or types do not exist
+struct Denial {
+    zone: LabelSeq,
+    nsec: Vec<(LabelSeq, NSECRecord)>,
+    /// Records with the hash their owner name stands for
+    nsec3: Vec<(Vec<u8>, NSEC3Record)>,
+}
+
+impl Denial {
+    fn from_section(
+        zone: &LabelSeq,
+        keys: &[DNSKEYRecord],
+        section: &[ResourceRecord],
+    ) -> Result<Denial, Error> {
+        let mut denial = Denial {
+            zone: zone.clone(),
+            nsec: vec![],
+            nsec3: vec![],
+        };
+
+        for rrset in rrsets(section) {
+            match &rrset.data[0] {
+                ResourceData::NSEC(nsec) => {
+                    check_signed(&rrset, section, zone, keys)?;
+                    denial.nsec.push((rrset.name.clone(), nsec.clone()));
+                }
+                ResourceData::NSEC3(nsec3) => {
+                    // NSEC3 owners are the hash of a name, just below the apex
+                    if rrset.name.parent().as_ref() != Some(zone) {
+                        continue;
+                    }
+                    check_signed(&rrset, section, zone, keys)?;
+                    let Ok(hash) =
+                        text::parse_base32hex(&String::from_utf8_lossy(&rrset.name.labels[0]))
+                    else {
+                        continue;
+                    };
+                    denial.nsec3.push((hash, nsec3.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        if denial.nsec.is_empty() && denial.nsec3.is_empty() {
+            return Err(bogus(format!("no proof of nonexistence from {zone}")));
+        }
+        Ok(denial)
+    }
+
+    /// Whether NSEC3 records are of no use, being hashed in a way this server does not do
+    /// or with too many iterations, which makes the answer insecure
+    fn is_nsec3_unusable(&self) -> bool {
+        self.nsec.is_empty()
+            && self.nsec3.iter().any(|(_, nsec3)| {
+                nsec3.hash_algorithm != NSEC3_HASH_SHA1 || nsec3.iterations > MAX_NSEC3_ITERATIONS
+            })
+    }
+
+    fn nsec_at(&self, name: &LabelSeq) -> Option<&NSECRecord> {
+        self.nsec
+            .iter()
+            .find(|(owner, _)| owner == name)
+            .map(|(_, nsec)| nsec)
+    }
+
+    fn nsec_covering(&self, name: &LabelSeq) -> Option<(&LabelSeq, &NSECRecord)> {
+        if !name.is_subdomain_of(&self.zone) {
+            return None;
+        }
+        self.nsec
+            .iter()
+            .filter(|(owner, nsec)| {
+                !(name.is_subdomain_of(owner) && hides_descendants(&nsec.types))
+            })
+            .find(|(owner, nsec)| dnssec::nsec_covers(owner, &nsec.next_domain, name))
+            .map(|(owner, nsec)| (owner, nsec))
+    }
+
+    fn hash(&self, name: &LabelSeq) -> Vec<u8> {
+        let (_, params) = &self.nsec3[0];
+        dnssec::nsec3_hash(name, &params.salt, params.iterations)
+    }
+
+    fn nsec3_at(&self, name: &LabelSeq) -> Option<&NSEC3Record> {
+        let hash = self.hash(name);
+        self.nsec3
+            .iter()
+            .find(|(owner, _)| *owner == hash)
+            .map(|(_, nsec3)| nsec3)
+    }
+
+    fn nsec3_covering(&self, name: &LabelSeq) -> Option<&NSEC3Record> {
+        let hash = self.hash(name);
+        self.nsec3
+            .iter()
+            .find(|(owner, nsec3)| dnssec::nsec3_covers(owner, &nsec3.next_hashed, &hash))
+            .map(|(_, nsec3)| nsec3)
+    }
+
+    /// Closest encloser of `name` and the NSEC3 covering the next closer name,
+    /// as per RFC 5155 section 8.3
+    fn closest_encloser(&self, name: &LabelSeq) -> Option<(LabelSeq, &NSEC3Record)> {
+        dnssec::enclosers(name, &self.zone).find_map(|(encloser, next_closer)| {
+            if hides_descendants(&self.nsec3_at(&encloser)?.types) {
+                return None;
+            }
+            Some((encloser, self.nsec3_covering(&next_closer)?))
+        })
+    }
+
+    /// Check that `name` does not exist, as per RFC 4035 section 5.4 and RFC 5155 section 8.4
+    fn prove_nxdomain(&self, name: &LabelSeq) -> Result<bool, Error> {
+        let fail = || bogus(format!("no proof that {name} does not exist"));
+
+        if !self.nsec.is_empty() {
+            let (owner, nsec) = self.nsec_covering(name).ok_or_else(fail)?;
+            let encloser = nsec_closest_encloser(name, owner, nsec);
+            self.nsec_covering(&dnssec::wildcard_of(&encloser))
+                .ok_or_else(fail)?;
+            return Ok(true);
+        }
+
+        if self.is_nsec3_unusable() {
+            return Ok(false);
+        }
+        let (encloser, next_closer) = self.closest_encloser(name).ok_or_else(fail)?;
+        self.nsec3_covering(&dnssec::wildcard_of(&encloser))
+            .ok_or_else(fail)?;
+        // Opt-out leaves unsigned delegations out of the chain, so they may exist
+        Ok(next_closer.flags & NSEC3_FLAG_OPT_OUT == 0)
+    }
+
+    /// Check that `name` has no record of type `qtype`,
+    /// as per RFC 4035 section 5.4 and RFC 5155 section 8.5 to 8.7
+    fn prove_nodata(&self, name: &LabelSeq, qtype: &FlagRecordType) -> Result<bool, Error> {
+        let fail = || bogus(format!("no proof that {name} has no {qtype}"));
+        // Below a zone cut, only the DS record is answered from the parent side
+        let lacks = |types: &[FlagRecordType]| {
+            !types.contains(qtype)
+                && !types.contains(&FlagRecordType::CNAME)
+                && (*qtype == FlagRecordType::DS || !is_delegation(types))
+        };
+
+        if !self.nsec.is_empty() {
+            if let Some(nsec) = self.nsec_at(name) {
+                return lacks(&nsec.types).then_some(true).ok_or_else(fail);
+            }
+            let (owner, nsec) = self.nsec_covering(name).ok_or_else(fail)?;
+            // Empty non-terminal, the next name being below it
+            if nsec.next_domain.is_subdomain_of(name) {
+                return Ok(true);
+            }
+            // Wildcard that has no record of the type either
+            let encloser = nsec_closest_encloser(name, owner, nsec);
+            let wildcard = self
+                .nsec_at(&dnssec::wildcard_of(&encloser))
+                .ok_or_else(fail)?;
+            return lacks(&wildcard.types).then_some(true).ok_or_else(fail);
+        }
+
+        if self.is_nsec3_unusable() {
+            return Ok(false);
+        }
+        if let Some(nsec3) = self.nsec3_at(name) {
+            return lacks(&nsec3.types).then_some(true).ok_or_else(fail);
+        }
+        let (encloser, next_closer) = self.closest_encloser(name).ok_or_else(fail)?;
+        if *qtype == FlagRecordType::DS && next_closer.flags & NSEC3_FLAG_OPT_OUT != 0 {
+            return Ok(false);
+        }
+        let wildcard = self
+            .nsec3_at(&dnssec::wildcard_of(&encloser))
+            .ok_or_else(fail)?;
+        lacks(&wildcard.types).then_some(true).ok_or_else(fail)
+    }
+
+    /// Check that `name`, answered from a wildcard with `labels` labels, does not exist
+    fn prove_expansion(&self, name: &LabelSeq, labels: u8) -> Result<bool, Error> {
+        let fail = || bogus(format!("no proof that wildcard applies to {name}"));
+
+        if !self.nsec.is_empty() {
+            self.nsec_covering(name).ok_or_else(fail)?;
+            return Ok(true);
+        }
+
+        if self.is_nsec3_unusable() {
+            return Ok(false);
+        }
+        let next_closer = LabelSeq {
+            labels: name.labels[name.labels.len() - labels as usize - 1..].to_vec(),
+        };
+        let nsec3 = self.nsec3_covering(&next_closer).ok_or_else(fail)?;
+        Ok(nsec3.flags & NSEC3_FLAG_OPT_OUT == 0)
+    }
+
+    /// Tell from the proof that `name` has no DS whether it is a delegation
+    /// to an unsigned zone, rather than a name within the zone
+    fn proves_unsigned_delegation(&self, name: &LabelSeq) -> Result<bool, Error> {
+        let fail = || bogus(format!("no proof that {name} has no DS"));
+
+        if !self.nsec.is_empty() {
+            if let Some(nsec) = self.nsec_at(name) {
+                if nsec.types.contains(&FlagRecordType::DS) {
+                    return Err(fail());
+                }
+                return Ok(is_delegation(&nsec.types));
+            }
+            self.nsec_covering(name).ok_or_else(fail)?;
+            return Ok(false);
+        }
+
+        if self.is_nsec3_unusable() {
+            return Ok(true);
+        }
+        if let Some(nsec3) = self.nsec3_at(name) {
+            if nsec3.types.contains(&FlagRecordType::DS) {
+                return Err(fail());
+            }
+            return Ok(is_delegation(&nsec3.types));
+        }
+        let (_, next_closer) = self.closest_encloser(name).ok_or_else(fail)?;
+        Ok(next_closer.flags & NSEC3_FLAG_OPT_OUT != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// Records of a fixture zone of the `testdata` directory
+    fn fixture(file: &str) -> Vec<ResourceRecord> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(file);
+        let content = fs::read_to_string(path).unwrap();
+        text::entries(&content)
+            .unwrap()
+            .iter()
+            .map(|entry| ResourceRecord::from_tokens(&entry.tokens, &LabelSeq::new()).unwrap())
+            .collect()
+    }
+
+    fn name(s: &str) -> LabelSeq {
+        LabelSeq::from_text(s, &LabelSeq::new()).unwrap()
+    }
+
+    fn keys(records: &[ResourceRecord]) -> Vec<DNSKEYRecord> {
+        records
+            .iter()
+            .filter_map(|r| match &r.data {
+                ResourceData::DNSKEY(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn denial(file: &str) -> Denial {
+        let records = fixture(file);
+        Denial::from_section(&name("example."), &keys(&records), &records).unwrap()
+    }
+
+    fn is_bogus<T>(result: Result<T, Error>) -> bool {
+        matches!(result, Err(Error::Bogus(_)))
+    }
+
+    #[test]
+    fn signatures_of_fixtures_verify() {
+        let apex = name("example.");
+        let cuts = [name("insecure.example."), name("secure.example.")];
+        for file in ["nsec.zone", "nsec3.zone"] {
+            let records = fixture(file);
+            let keys = keys(&records);
+            for rrset in rrsets(&records) {
+                // NS of delegations and glue below them are not signed
+                let is_glue = cuts
+                    .iter()
+                    .any(|cut| rrset.name != *cut && rrset.name.is_subdomain_of(cut));
+                if rrset.name != apex && rrset.record_type == FlagRecordType::NS || is_glue {
+                    continue;
+                }
+                let rrsig = signatures(&rrset, &records)[0];
+                let data = dnssec::signed_data(rrsig, &rrset);
+                assert!(
+                    dnssec::verify_signature(&keys[0], &data, &rrsig.signature),
+                    "{file}: {} {}",
+                    rrset.name,
+                    rrset.record_type
+                );
+                check_signed(&rrset, &records, &apex, &keys).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn wildcard_expansion_verifies() {
+        let records = fixture("nsec.zone");
+        let keys = keys(&records);
+        let mut rrset = find_rrset(&records, &name("*.wild.example."), FlagRecordType::A).unwrap();
+        let rrsig = signatures(&rrset, &records)[0].clone();
+
+        // As answered for a name the wildcard stands for
+        rrset.name = name("a.wild.example.");
+        assert!(verify_with(&rrset, &rrsig, &keys));
+        assert_eq!(rrsig.labels, 2);
+    }
+
+    #[test]
+    fn tampered_rdata_is_bogus() {
+        let mut records = fixture("nsec.zone");
+        let keys = keys(&records);
+        for record in &mut records {
+            if record.name == name("www.example.") && record.record_type == FlagRecordType::A {
+                record.data = ResourceData::A("192.0.2.66".parse().unwrap());
+            }
+        }
+        let rrset = find_rrset(&records, &name("www.example."), FlagRecordType::A).unwrap();
+        assert!(is_bogus(check_signed(
+            &rrset,
+            &records,
+            &name("example."),
+            &keys
+        )));
+
+        // A proof of nonexistence that was changed cannot be used either
+        let mut records = fixture("nsec.zone");
+        for record in &mut records {
+            if let ResourceData::NSEC(nsec) = &mut record.data {
+                nsec.types.retain(|t| *t != FlagRecordType::A);
+            }
+        }
+        assert!(is_bogus(Denial::from_section(
+            &name("example."),
+            &keys,
+            &records
+        )));
+    }
+
+    #[test]
+    fn nsec_nxdomain() {
+        let denial = denial("nsec.zone");
+        assert!(denial.prove_nxdomain(&name("nope.example.")).unwrap());
+        assert!(denial.prove_nxdomain(&name("a.nope.example.")).unwrap());
+        assert!(is_bogus(denial.prove_nxdomain(&name("www.example."))));
+        // The wildcard answers for names below wild.example.
+        assert!(is_bogus(denial.prove_nxdomain(&name("a.wild.example."))));
+    }
+
+    #[test]
+    fn nsec3_nxdomain() {
+        let denial = denial("nsec3.zone");
+        assert!(denial.prove_nxdomain(&name("nope.example.")).unwrap());
+        assert!(denial.prove_nxdomain(&name("a.nope.example.")).unwrap());
+        assert!(is_bogus(denial.prove_nxdomain(&name("www.example."))));
+        assert!(is_bogus(denial.prove_nxdomain(&name("a.wild.example."))));
+    }
+
+    #[test]
+    fn nsec_nodata() {
+        let denial = denial("nsec.zone");
+        let www = name("www.example.");
+        assert!(denial.prove_nodata(&www, &FlagRecordType::MX).unwrap());
+        assert!(is_bogus(denial.prove_nodata(&www, &FlagRecordType::A)));
+        // Empty non-terminal
+        let ent = name("wild.example.");
+        assert!(denial.prove_nodata(&ent, &FlagRecordType::A).unwrap());
+        // Wildcard without the type
+        let expanded = name("a.wild.example.");
+        assert!(denial
+            .prove_nodata(&expanded, &FlagRecordType::TXT)
+            .unwrap());
+        assert!(is_bogus(denial.prove_nodata(&expanded, &FlagRecordType::A)));
+    }
+
+    #[test]
+    fn nsec3_nodata() {
+        let denial = denial("nsec3.zone");
+        let www = name("www.example.");
+        assert!(denial.prove_nodata(&www, &FlagRecordType::MX).unwrap());
+        assert!(is_bogus(denial.prove_nodata(&www, &FlagRecordType::A)));
+        let ent = name("wild.example.");
+        assert!(denial.prove_nodata(&ent, &FlagRecordType::A).unwrap());
+        let expanded = name("a.wild.example.");
+        assert!(denial
+            .prove_nodata(&expanded, &FlagRecordType::TXT)
+            .unwrap());
+        assert!(is_bogus(denial.prove_nodata(&expanded, &FlagRecordType::A)));
+    }
+
+    #[test]
+    fn nsec_expansion() {
+        let denial = denial("nsec.zone");
+        assert!(denial.prove_expansion(&name("a.wild.example."), 2).unwrap());
+        assert!(is_bogus(denial.prove_expansion(&name("www.example."), 1)));
+    }
+
+    #[test]
+    fn nsec3_expansion() {
+        let denial = denial("nsec3.zone");
+        assert!(denial.prove_expansion(&name("a.wild.example."), 2).unwrap());
+        assert!(is_bogus(denial.prove_expansion(&name("www.example."), 1)));
+    }
+
+    #[test]
+    fn nsec_unsigned_delegation() {
+        let denial = denial("nsec.zone");
+        assert!(denial
+            .proves_unsigned_delegation(&name("insecure.example."))
+            .unwrap());
+        assert!(!denial
+            .proves_unsigned_delegation(&name("www.example."))
+            .unwrap());
+        assert!(!denial
+            .proves_unsigned_delegation(&name("nope.example."))
+            .unwrap());
+        assert!(is_bogus(
+            denial.proves_unsigned_delegation(&name("secure.example."))
+        ));
+    }
+
+    #[test]
+    fn nsec3_unsigned_delegation() {
+        let denial = denial("nsec3.zone");
+        assert!(denial
+            .proves_unsigned_delegation(&name("insecure.example."))
+            .unwrap());
+        assert!(!denial
+            .proves_unsigned_delegation(&name("www.example."))
+            .unwrap());
+        assert!(!denial
+            .proves_unsigned_delegation(&name("nope.example."))
+            .unwrap());
+        assert!(is_bogus(
+            denial.proves_unsigned_delegation(&name("secure.example."))
+        ));
+    }
+
+    /// Check that the NSEC or NSEC3 of the delegation to `insecure.example.`
+    /// proves nothing but the lack of DS, then the same once it is made a DNAME
+    fn check_below_cut(mut denial: Denial) {
+        let cut = name("insecure.example.");
+        assert!(is_bogus(denial.prove_nodata(&cut, &FlagRecordType::A)));
+        assert!(denial.prove_nodata(&cut, &FlagRecordType::DS).unwrap());
+        let below = name("a.insecure.example.");
+        assert!(is_bogus(denial.prove_nxdomain(&below)));
+        assert!(is_bogus(denial.prove_nodata(&below, &FlagRecordType::A)));
+        assert!(is_bogus(denial.prove_nodata(&below, &FlagRecordType::DS)));
+
+        let dname = vec![FlagRecordType::DNAME, FlagRecordType::NSEC];
+        for (owner, nsec) in &mut denial.nsec {
+            if *owner == cut {
+                nsec.types = dname.clone();
+            }
+        }
+        if !denial.nsec3.is_empty() {
+            let hash = denial.hash(&cut);
+            for (owner, nsec3) in &mut denial.nsec3 {
+                if *owner == hash {
+                    nsec3.types = dname.clone();
+                }
+            }
+        }
+        assert!(denial.prove_nodata(&cut, &FlagRecordType::A).unwrap());
+        assert!(is_bogus(denial.prove_nxdomain(&below)));
+        assert!(is_bogus(denial.prove_nodata(&below, &FlagRecordType::A)));
+    }
+
+    #[test]
+    fn nsec_below_cut() {
+        check_below_cut(denial("nsec.zone"));
+    }
+
+    #[test]
+    fn nsec3_below_cut() {
+        check_below_cut(denial("nsec3.zone"));
+    }
+
+    fn record(s: &str) -> ResourceRecord {
+        s.parse().unwrap()
+    }
+
+    /// Reply to `question` with the records of `answer` and `authority`
+    fn reply(question: &Question, answer: &[ResourceRecord], authority: &[&str]) -> Message {
+        let mut reply = Message::new();
+        reply.add_question(question.clone());
+        for r in answer {
+            reply.add_resource(r.clone());
+        }
+        for r in authority {
+            reply.add_auth_resource(record(r));
+        }
+        reply
+    }
+
+    #[tokio::test]
+    async fn cname_out_of_anchor_is_insecure() {
+        let records = fixture("nsec.zone");
+        let apex = name("example.");
+        let anchor = TrustAnchor {
+            owner: apex.clone(),
+            ds: vec![],
+            keys: keys(&records),
+        };
+        // Keys of the anchor known beforehand, so that nothing is looked up
+        let secure = Keys::Secure {
+            zone: apex.clone(),
+            keys: keys(&records),
+        };
+        cache_keys(apex, secure, MAX_CACHE_TTL);
+        let cfg = Config::parse_from(["dinosaurust"]);
+        let validator = Validator {
+            cfg: &cfg,
+            anchor: &anchor,
+        };
+
+        let question = Question::new(name("cname.example."), FlagRecordType::A);
+        let mut answer: Vec<ResourceRecord> = records
+            .iter()
+            .filter(|r| r.name == question.name)
+            .filter(|r| match &r.data {
+                ResourceData::CNAME(_) => true,
+                ResourceData::RRSIG(rrsig) => rrsig.type_covered == FlagRecordType::CNAME,
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        assert_eq!(answer.len(), 2);
+        let validate = |reply: Message| {
+            let question = question.clone();
+            let validator = &validator;
+            async move { validator.validate(&question, &reply).await }
+        };
+        // Target left for the client to ask about
+        assert!(validate(reply(&question, &answer, &[])).await.unwrap());
+
+        // Unsigned target
+        answer.push(record("www.example.org. 3600 IN A 192.0.2.80"));
+        assert!(!validate(reply(&question, &answer, &[])).await.unwrap());
+
+        // Target signed in its own zone
+        answer.push(record(
+            "www.example.org. 3600 IN RRSIG A 15 3 3600 20600101000000 20260101000000 \
+             4242 example.org. AAAA",
+        ));
+        assert!(!validate(reply(&question, &answer, &[])).await.unwrap());
+
+        // Negative answer for the target from its own zone
+        answer.truncate(2);
+        let authority = [
+            "example.org. 300 IN SOA ns.example.org. admin.example.org. 1 3600 900 604800 300",
+            "example.org. 300 IN RRSIG SOA 15 2 300 20600101000000 20260101000000 \
+             4242 example.org. AAAA",
+        ];
+        assert!(!validate(reply(&question, &answer, &authority))
+            .await
+            .unwrap());
+        let mut nxdomain = reply(&question, &answer, &authority);
+        nxdomain.header.set_rcode(FlagRCode::NXDOMAIN);
+        assert!(!validate(nxdomain).await.unwrap());
+    }
+}
@@ -0,0 +1,775 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use log::warn;
+use static_init::dynamic;
+use tokio::sync::broadcast;
+
+use crate::common::{FlagAA, FlagClassCode, FlagRCode, FlagRecordType, LabelSeq};
+use crate::dnssec;
+use crate::error::Error;
+use crate::message::Message;
+use crate::question::Question;
+use crate::resourserecord::{ResourceData, ResourceRecord, SOARecord};
+use crate::text::{self, Entry, RecordHead};
+
+/// Longest chain of CNAME followed inside a zone before giving up
+const MAX_CNAME_CHAIN: usize = 8;
+/// Deepest nesting of `$INCLUDE` directives
+const MAX_INCLUDE_DEPTH: usize = 8;
+/// Zone changes kept for a slow subscriber before it misses some
+const MAX_PENDING_CHANGES: usize = 64;
+
+/// Zone this server is authoritative for
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub origin: LabelSeq,
+    pub class_code: FlagClassCode,
+    /// Records by owner name
+    records: HashMap<LabelSeq, Vec<ResourceRecord>>,
+    /// Owner names and all their ancestors up to the origin, to tell empty non-terminals
+    /// from names that do not exist
+    nodes: HashSet<LabelSeq>,
+}
+
+impl Zone {
+    /// Build a zone from its records, which must have exactly one SOA at the apex.
+    /// Records outside the zone are dropped
+    pub fn from_records(records: Vec<ResourceRecord>) -> Result<Zone, Error> {
+        let mut soas = records
+            .iter()
+            .filter(|r| r.record_type == FlagRecordType::SOA);
+        let (Some(soa), None) = (soas.next(), soas.next()) else {
+            return Err(Error::BadZone(
+                "zone must have exactly one SOA record".to_string(),
+            ));
+        };
+
+        let mut zone = Zone {
+            origin: soa.name.clone(),
+            class_code: soa.class_code.clone(),
+            records: HashMap::new(),
+            nodes: HashSet::new(),
+        };
+
+        for record in records {
+            if !record.name.is_subdomain_of(&zone.origin) {
+                warn!("Ignore record out of zone {}: {record}", zone.origin);
+                continue;
+            }
+            if record.class_code != zone.class_code {
+                return Err(Error::BadZone(format!(
+                    "record class does not match zone class: {record}"
+                )));
+            }
+            zone.insert(record);
+        }
+
+        Ok(zone)
+    }
+
+    /// Load a zone from a master file as per RFC 1035 section 5
+    ///
+    /// Names are relative to the root until the first `$ORIGIN`,
+    /// and the zone origin is the owner of its SOA record
+    pub fn from_file(path: &Path) -> Result<Zone, Error> {
+        let mut reader = ZoneFileReader::default();
+        reader.read_file(path, LabelSeq::new(), 0)?;
+        Zone::from_records(reader.records)
+    }
+
+    fn insert(&mut self, record: ResourceRecord) {
+        let mut name = record.name.clone();
+        while self.nodes.insert(name.clone()) && name != self.origin {
+            name.labels.remove(0);
+        }
+        self.records
+            .entry(record.name.clone())
+            .or_default()
+            .push(record);
+    }
+
+    pub fn soa(&self) -> &ResourceRecord {
+        self.records[&self.origin]
+            .iter()
+            .find(|r| r.record_type == FlagRecordType::SOA)
+            .expect("zone always has an SOA at the apex")
+    }
+
+    pub fn soa_data(&self) -> &SOARecord {
+        match &self.soa().data {
+            ResourceData::SOA(soa) => soa,
+            _ => unreachable!("SOA record always holds SOA data"),
+        }
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.soa_data().serial
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.records.values().flatten()
+    }
+
+    fn get(&self, name: &LabelSeq, record_type: &FlagRecordType) -> Vec<ResourceRecord> {
+        self.records
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|r| &r.record_type == record_type)
+            .cloned()
+            .collect()
+    }
+
+    /// Topmost zone cut between the origin and `name`, if any
+    fn find_cut(&self, name: &LabelSeq) -> Option<LabelSeq> {
+        let depth = name.labels.len() - self.origin.labels.len();
+        (0..depth).rev().find_map(|i| {
+            let cut = LabelSeq {
+                labels: name.labels[i..].to_vec(),
+            };
+            let has_ns = self
+                .records
+                .get(&cut)?
+                .iter()
+                .any(|r| r.record_type == FlagRecordType::NS);
+            has_ns.then_some(cut)
+        })
+    }
+
+    /// Answer a question about a name in this zone, as per RFC 1034 section 4.3.2
+    ///
+    /// AA is set unless the name is delegated to a child zone,
+    /// in which case the reply is a referral with glue addresses.
+    /// Names that do not exist are answered from a wildcard, if any.
+    /// When `dnssec_ok` is set, signatures and proofs of nonexistence of a signed zone
+    /// are added as per RFC 4035 section 3.1
+    pub fn answer(&self, question: &Question, dnssec_ok: bool, reply: &mut Message) {
+        let qtype = FlagRecordType::from_bits_retain(question.record_type);
+        let mut name = question.name.clone();
+        reply.header.set_aa(FlagAA::TRUE);
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            // The parent side answers for the DS records of a cut
+            let cut = self
+                .find_cut(&name)
+                .filter(|cut| qtype != FlagRecordType::DS || *cut != name);
+            if let Some(cut) = cut {
+                if reply.resources.is_empty() {
+                    reply.header.set_aa(FlagAA::FALSE);
+                }
+                let servers = self.get(&cut, &FlagRecordType::NS);
+                self.add_addresses(&servers, reply);
+                for ns in servers {
+                    reply.add_auth_resource(ns);
+                }
+                if dnssec_ok {
+                    let ds = self.get(&cut, &FlagRecordType::DS);
+                    if ds.is_empty() {
+                        self.add_proof(self.nodata_proof(&cut), reply);
+                    } else {
+                        for record in ds {
+                            reply.add_auth_resource(record);
+                        }
+                        for rrsig in self.signatures(&cut, &FlagRecordType::DS) {
+                            reply.add_auth_resource(rrsig);
+                        }
+                    }
+                }
+                return;
+            }
+
+            let mut synthesized = false;
+            let records = match self.records.get(&name) {
+                Some(records) => records.clone(),
+                None if self.nodes.contains(&name) => vec![],
+                None => match self.synthesize(&name) {
+                    Some(records) => {
+                        synthesized = true;
+                        records
+                    }
+                    None => {
+                        reply.header.set_rcode(FlagRCode::NXDOMAIN);
+                        self.add_negative_soa(dnssec_ok, reply);
+                        if dnssec_ok {
+                            self.add_proof(self.nxdomain_proof(&name), reply);
+                        }
+                        return;
+                    }
+                },
+            };
+
+            let answers: Vec<ResourceRecord> = records
+                .iter()
+                .filter(|r| qtype == FlagRecordType::ANY || r.record_type == qtype)
+                .cloned()
+                .collect();
+            if !answers.is_empty() {
+                self.add_addresses(&answers, reply);
+                for answer in answers {
+                    reply.add_resource(answer);
+                }
+                if dnssec_ok && qtype != FlagRecordType::ANY {
+                    for rrsig in covering(&records, &qtype) {
+                        reply.add_resource(rrsig);
+                    }
+                }
+                if dnssec_ok && synthesized {
+                    self.add_proof(self.expansion_proof(&name), reply);
+                }
+                return;
+            }
+
+            let Some(cname) = records
+                .iter()
+                .find(|r| r.record_type == FlagRecordType::CNAME)
+            else {
+                self.add_negative_soa(dnssec_ok, reply);
+                if dnssec_ok {
+                    let proof = match synthesized {
+                        true => self.wildcard_nodata_proof(&name),
+                        false => self.nodata_proof(&name),
+                    };
+                    self.add_proof(proof, reply);
+                }
+                return;
+            };
+            reply.add_resource(cname.clone());
+            if dnssec_ok {
+                for rrsig in covering(&records, &FlagRecordType::CNAME) {
+                    reply.add_resource(rrsig);
+                }
+                if synthesized {
+                    self.add_proof(self.expansion_proof(&name), reply);
+                }
+            }
+            let ResourceData::CNAME(target) = &cname.data else {
+                return;
+            };
+            if !target.is_subdomain_of(&self.origin) {
+                return;
+            }
+            name = target.clone();
+        }
+    }
+
+    /// Records for `name` synthesized from the wildcard at its closest encloser,
+    /// as per RFC 4592 section 3.3.1
+    ///
+    /// `name` must not exist in the zone.
+    /// Return `None` if there is no such wildcard, and no record if the wildcard
+    /// is an empty non-terminal
+    fn synthesize(&self, name: &LabelSeq) -> Option<Vec<ResourceRecord>> {
+        let source = dnssec::wildcard_of(&self.closest_encloser(name)?);
+        if !self.nodes.contains(&source) {
+            return None;
+        }
+
+        let records = self.records.get(&source).into_iter().flatten();
+        Some(
+            records
+                .map(|r| ResourceRecord {
+                    name: name.clone(),
+                    ..r.clone()
+                })
+                .collect(),
+        )
+    }
+
+    /// Deepest ancestor of `name` that exists in the zone, `name` itself left out
+    fn closest_encloser(&self, name: &LabelSeq) -> Option<LabelSeq> {
+        dnssec::enclosers(name, &self.origin)
+            .map(|(encloser, _)| encloser)
+            .find(|encloser| self.nodes.contains(encloser))
+    }
+
+    /// RRSIG records of `name` covering `record_type`
+    fn signatures(&self, name: &LabelSeq, record_type: &FlagRecordType) -> Vec<ResourceRecord> {
+        let records = self
+            .records
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        covering(records, record_type)
+    }
+
+    /// Add the SOA of a negative answer to the authority section, with its signatures
+    fn add_negative_soa(&self, dnssec_ok: bool, reply: &mut Message) {
+        reply.add_auth_resource(self.negative_soa());
+        if dnssec_ok {
+            for rrsig in self.signatures(&self.origin, &FlagRecordType::SOA) {
+                reply.add_auth_resource(rrsig);
+            }
+        }
+    }
+
+    /// Add NSEC or NSEC3 records to the authority section with their signatures,
+    /// once even if they prove several things
+    fn add_proof(&self, proof: Vec<&ResourceRecord>, reply: &mut Message) {
+        let mut owners = HashSet::new();
+        for record in proof {
+            if !owners.insert(&record.name) {
+                continue;
+            }
+            reply.add_auth_resource(record.clone());
+            for rrsig in self.signatures(&record.name, &record.record_type) {
+                reply.add_auth_resource(rrsig);
+            }
+        }
+    }
+
+    fn nsec_at(&self, name: &LabelSeq) -> Option<&ResourceRecord> {
+        self.records
+            .get(name)?
+            .iter()
+            .find(|r| r.record_type == FlagRecordType::NSEC)
+    }
+
+    fn nsec_covering(&self, name: &LabelSeq) -> Option<&ResourceRecord> {
+        self.records().find(|r| match &r.data {
+            ResourceData::NSEC(nsec) => dnssec::nsec_covers(&r.name, &nsec.next_domain, name),
+            _ => false,
+        })
+    }
+
+    /// Salt and iterations of the NSEC3 chain, if the zone is signed with one
+    fn nsec3_params(&self) -> Option<(&[u8], u16)> {
+        self.records().find_map(|r| match &r.data {
+            ResourceData::NSEC3(nsec3) => Some((&nsec3.salt[..], nsec3.iterations)),
+            _ => None,
+        })
+    }
+
+    fn nsec3_hash(&self, name: &LabelSeq) -> Vec<u8> {
+        let (salt, iterations) = self.nsec3_params().unwrap_or_default();
+        dnssec::nsec3_hash(name, salt, iterations)
+    }
+
+    fn nsec3_at(&self, name: &LabelSeq) -> Option<&ResourceRecord> {
+        let mut owner = self.origin.clone();
+        let label = text::to_base32hex(&self.nsec3_hash(name)).to_lowercase();
+        owner.labels.insert(0, label.into_bytes());
+        self.records
+            .get(&owner)?
+            .iter()
+            .find(|r| r.record_type == FlagRecordType::NSEC3)
+    }
+
+    fn nsec3_covering(&self, name: &LabelSeq) -> Option<&ResourceRecord> {
+        let hash = self.nsec3_hash(name);
+        self.records().find(|r| {
+            let ResourceData::NSEC3(nsec3) = &r.data else {
+                return false;
+            };
+            let Ok(owner) = text::parse_base32hex(&String::from_utf8_lossy(&r.name.labels[0]))
+            else {
+                return false;
+            };
+            dnssec::nsec3_covers(&owner, &nsec3.next_hashed, &hash)
+        })
+    }
+
+    /// NSEC3 records matching the closest encloser of `name` and covering the next
+    /// closer name, as per RFC 5155 section 7.2.1
+    fn closest_encloser_proof(&self, name: &LabelSeq) -> (Option<LabelSeq>, Vec<&ResourceRecord>) {
+        for (encloser, next_closer) in dnssec::enclosers(name, &self.origin) {
+            let Some(matching) = self.nsec3_at(&encloser) else {
+                continue;
+            };
+            let proof = [Some(matching), self.nsec3_covering(&next_closer)];
+            return (Some(encloser), proof.into_iter().flatten().collect());
+        }
+        (None, vec![])
+    }
+
+    /// Records proving that `name` does not exist and that no wildcard applies to it,
+    /// as per RFC 4035 section 3.1.3.2 and RFC 5155 section 7.2.2
+    fn nxdomain_proof(&self, name: &LabelSeq) -> Vec<&ResourceRecord> {
+        if self.nsec3_params().is_none() {
+            let wildcard = self
+                .closest_encloser(name)
+                .map(|ce| dnssec::wildcard_of(&ce));
+            let wildcard = wildcard.and_then(|w| self.nsec_covering(&w));
+            return [self.nsec_covering(name), wildcard]
+                .into_iter()
+                .flatten()
+                .collect();
+        }
+        let (encloser, mut proof) = self.closest_encloser_proof(name);
+        proof.extend(encloser.and_then(|ce| self.nsec3_covering(&dnssec::wildcard_of(&ce))));
+        proof
+    }
+
+    /// Records proving that `name` has no record of the type asked,
+    /// as per RFC 4035 section 3.1.3.1 and RFC 5155 sections 7.2.3 to 7.2.5
+    fn nodata_proof(&self, name: &LabelSeq) -> Vec<&ResourceRecord> {
+        if self.nsec3_params().is_none() {
+            // Empty non-terminals have no NSEC, the one covering them proves they exist
+            let nsec = self.nsec_at(name).or_else(|| self.nsec_covering(name));
+            return nsec.into_iter().collect();
+        }
+        match self.nsec3_at(name) {
+            Some(nsec3) => vec![nsec3],
+            // Delegation left out of an opt-out chain
+            None => self.closest_encloser_proof(name).1,
+        }
+    }
+
+    /// Records proving that `name`, answered from a wildcard, does not exist itself,
+    /// as per RFC 4035 section 3.1.3.3 and RFC 5155 section 7.2.6
+    fn expansion_proof(&self, name: &LabelSeq) -> Vec<&ResourceRecord> {
+        if self.nsec3_params().is_none() {
+            return self.nsec_covering(name).into_iter().collect();
+        }
+        let Some(encloser) = self.closest_encloser(name) else {
+            return vec![];
+        };
+        let next_closer = LabelSeq {
+            labels: name.labels[name.labels.len() - encloser.labels.len() - 1..].to_vec(),
+        };
+        self.nsec3_covering(&next_closer).into_iter().collect()
+    }
+
+    /// Records proving that `name` does not exist and that the wildcard answering for it
+    /// has no record of the type asked, as per RFC 4035 section 3.1.3.4
+    /// and RFC 5155 section 7.2.5
+    fn wildcard_nodata_proof(&self, name: &LabelSeq) -> Vec<&ResourceRecord> {
+        let Some(wildcard) = self
+            .closest_encloser(name)
+            .map(|ce| dnssec::wildcard_of(&ce))
+        else {
+            return vec![];
+        };
+        if self.nsec3_params().is_none() {
+            let proof = [self.nsec_covering(name), self.nsec_at(&wildcard)];
+            return proof.into_iter().flatten().collect();
+        }
+        let (_, mut proof) = self.closest_encloser_proof(name);
+        proof.extend(self.nsec3_at(&wildcard));
+        proof
+    }
+
+    /// SOA to put in negative answers, with the TTL of RFC 2308 section 3
+    fn negative_soa(&self) -> ResourceRecord {
+        let mut soa = self.soa().clone();
+        if let ResourceData::SOA(SOARecord { minimum, .. }) = soa.data {
+            soa.ttl = soa.ttl.min(minimum);
+        }
+        soa
+    }
+
+    /// Add to the additional section the addresses this zone knows
+    /// for the names in NS, MX and SRV records
+    fn add_addresses(&self, records: &[ResourceRecord], reply: &mut Message) {
+        for record in records {
+            let target = match &record.data {
+                ResourceData::NS(name) => name,
+                ResourceData::MX(mx) => &mx.exchange,
+                ResourceData::SRV(srv) => &srv.target,
+                _ => continue,
+            };
+            if !target.is_subdomain_of(&self.origin) {
+                continue;
+            }
+            for record_type in [FlagRecordType::A, FlagRecordType::AAAA] {
+                for address in self.get(target, &record_type) {
+                    reply.add_addi_resource(address);
+                }
+            }
+        }
+    }
+}
+
+/// RRSIG records among `records` covering `record_type`
+fn covering(records: &[ResourceRecord], record_type: &FlagRecordType) -> Vec<ResourceRecord> {
+    records
+        .iter()
+        .filter(
+            |r| matches!(&r.data, ResourceData::RRSIG(rrsig) if rrsig.type_covered == *record_type),
+        )
+        .cloned()
+        .collect()
+}
+
+/// Whether serial `a` is greater than serial `b`, as per RFC 1982 section 3.2
+pub fn serial_gt(a: u32, b: u32) -> bool {
+    a != b && (a.wrapping_sub(b) as i32) > 0
+}
+
+/// State of the master file parser that carries over from one entry to the next
+#[derive(Default)]
+struct ZoneFileReader {
+    default_ttl: Option<u32>,
+    last_owner: Option<LabelSeq>,
+    last_ttl: Option<u32>,
+    last_class: Option<FlagClassCode>,
+    records: Vec<ResourceRecord>,
+}
+
+impl ZoneFileReader {
+    fn read_file(&mut self, path: &Path, mut origin: LabelSeq, depth: usize) -> Result<(), Error> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            Error::Io(io::Error::new(
+                err.kind(),
+                format!("{}: {err}", path.display()),
+            ))
+        })?;
+        let at =
+            |line: usize, err: Error| Error::BadZone(format!("{}:{line}: {err}", path.display()));
+
+        let entries = text::entries(&content)
+            .map_err(|err| Error::BadZone(format!("{}: {err}", path.display())))?;
+        for entry in entries {
+            let line = entry.line;
+            match entry.tokens[0].text.as_str() {
+                "$ORIGIN" if !entry.owner_omitted => {
+                    let name = directive_arg(&entry, 1).map_err(|err| at(line, err))?;
+                    origin = LabelSeq::from_text(name, &origin).map_err(|err| at(line, err))?;
+                }
+                "$TTL" if !entry.owner_omitted => {
+                    let ttl = directive_arg(&entry, 1).map_err(|err| at(line, err))?;
+                    self.default_ttl = Some(text::parse_ttl(ttl).map_err(|err| at(line, err))?);
+                }
+                "$INCLUDE" if !entry.owner_omitted => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(at(line, Error::BadZone("too many nested $INCLUDE".into())));
+                    }
+                    let file = directive_arg(&entry, 1).map_err(|err| at(line, err))?;
+                    let file = path.parent().unwrap_or(Path::new(".")).join(file);
+                    let include_origin = match entry.tokens.get(2) {
+                        Some(token) => LabelSeq::from_text(&token.text, &origin)
+                            .map_err(|err| at(line, err))?,
+                        None => origin.clone(),
+                    };
+                    // The origin of the including file is left unchanged, as per RFC 1035
+                    self.read_file(&file, include_origin, depth + 1)?;
+                }
+                directive if directive.starts_with('$') && !entry.owner_omitted => {
+                    return Err(at(
+                        line,
+                        Error::BadZone(format!("unsupported directive {directive}")),
+                    ));
+                }
+                _ => {
+                    let record = self
+                        .read_record(&entry, &origin)
+                        .map_err(|err| at(line, err))?;
+                    self.records.push(record);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a record whose owner, TTL and class may be left out,
+    /// in which case they are the ones of the previous record
+    fn read_record(&mut self, entry: &Entry, origin: &LabelSeq) -> Result<ResourceRecord, Error> {
+        let mut tokens = &entry.tokens[..];
+        let name = if entry.owner_omitted {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| Error::BadZone("missing owner name".to_string()))?
+        } else {
+            let name = LabelSeq::from_text(&tokens[0].text, origin)?;
+            tokens = &tokens[1..];
+            name
+        };
+
+        let (head, rest) = RecordHead::parse(tokens)?;
+        let ttl = head
+            .ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .ok_or_else(|| Error::BadZone("missing TTL and no $TTL".to_string()))?;
+        let class_code = head
+            .class_code
+            .or(self.last_class.clone())
+            .unwrap_or(FlagClassCode::IN);
+        let data = ResourceData::from_tokens(head.record_type.clone(), rest, origin)?;
+
+        self.last_owner = Some(name.clone());
+        if head.ttl.is_some() {
+            self.last_ttl = head.ttl;
+        }
+        self.last_class = Some(class_code.clone());
+
+        Ok(ResourceRecord {
+            name,
+            record_type: head.record_type,
+            class_code,
+            ttl,
+            data,
+        })
+    }
+}
+
+fn directive_arg(entry: &Entry, idx: usize) -> Result<&str, Error> {
+    entry
+        .tokens
+        .get(idx)
+        .map(|token| token.text.as_str())
+        .ok_or_else(|| Error::BadZone(format!("missing argument to {}", entry.tokens[0].text)))
+}
+
+/// Zones this server is authoritative for, keyed by origin
+///
+/// Zones are shared behind `Arc`, so that a lookup does not hold the lock
+/// and a zone is replaced as a whole when it changes.
+/// The origin of each zone added or whose serial changed is sent to subscribers
+pub struct ZoneStore {
+    zones: RwLock<HashMap<LabelSeq, Arc<Zone>>>,
+    changes: broadcast::Sender<LabelSeq>,
+}
+
+impl ZoneStore {
+    pub fn new() -> ZoneStore {
+        let (changes, _) = broadcast::channel(MAX_PENDING_CHANGES);
+        ZoneStore {
+            zones: RwLock::new(HashMap::new()),
+            changes,
+        }
+    }
+
+    pub fn insert(&self, zone: Zone) {
+        let origin = zone.origin.clone();
+        let serial = zone.serial();
+
+        let mut zones = self.zones.write().unwrap();
+        let previous = zones.insert(origin.clone(), Arc::new(zone));
+        drop(zones);
+
+        if previous.is_none_or(|zone| zone.serial() != serial) {
+            // Nobody may be listening, which is fine
+            let _ = self.changes.send(origin);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LabelSeq> {
+        self.changes.subscribe()
+    }
+
+    pub fn remove(&self, origin: &LabelSeq) -> Option<Arc<Zone>> {
+        self.zones.write().unwrap().remove(origin)
+    }
+
+    pub fn get(&self, origin: &LabelSeq) -> Option<Arc<Zone>> {
+        self.zones.read().unwrap().get(origin).cloned()
+    }
+
+    /// Find the deepest zone that `name` belongs to
+    pub fn find(&self, name: &LabelSeq) -> Option<Arc<Zone>> {
+        let zones = self.zones.read().unwrap();
+        if zones.is_empty() {
+            return None;
+        }
+
+        (0..=name.labels.len()).find_map(|i| {
+            let origin = LabelSeq {
+                labels: name.labels[i..].to_vec(),
+            };
+            zones.get(&origin).cloned()
+        })
+    }
+}
+
+impl Default for ZoneStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[dynamic]
+pub static ZONES: ZoneStore = ZoneStore::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOA: &str = "example. 3600 IN SOA ns.example. admin.example. 1 7200 3600 1209600 300";
+
+    fn zone(records: &[&str]) -> Zone {
+        let records = [SOA].iter().chain(records);
+        Zone::from_records(records.map(|r| r.parse().unwrap()).collect()).unwrap()
+    }
+
+    fn ask(zone: &Zone, name: &str, qtype: FlagRecordType) -> Message {
+        let question = Question::new(LabelSeq::from_string(name), qtype);
+        let mut reply = Message::new();
+        zone.answer(&question, false, &mut reply);
+        reply
+    }
+
+    fn answers(reply: &Message) -> Vec<String> {
+        reply.resources.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn wildcard_answers_missing_names() {
+        let zone = zone(&["*.example. 300 IN A 192.0.2.1"]);
+        for name in ["foo.example.", "a.b.example."] {
+            let reply = ask(&zone, name, FlagRecordType::A);
+            assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOERROR);
+            assert!(reply.header.get_aa().bits() != 0);
+            assert_eq!(answers(&reply), [format!("{name}\t300\tIN\tA\t192.0.2.1")]);
+        }
+
+        // The wildcard matches no other type
+        let reply = ask(&zone, "foo.example.", FlagRecordType::MX);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOERROR);
+        assert!(reply.resources.is_empty());
+        assert_eq!(reply.auth_resources[0].record_type, FlagRecordType::SOA);
+    }
+
+    #[test]
+    fn existing_names_take_precedence_over_wildcard() {
+        let zone = zone(&[
+            "*.example. 300 IN A 192.0.2.1",
+            "www.example. 300 IN A 192.0.2.2",
+            "mail.example. 300 IN MX 10 mx.example.",
+        ]);
+        let reply = ask(&zone, "www.example.", FlagRecordType::A);
+        assert_eq!(answers(&reply), ["www.example.\t300\tIN\tA\t192.0.2.2"]);
+
+        // Name exists with other types only, as per RFC 4592 section 2.2.1
+        let reply = ask(&zone, "mail.example.", FlagRecordType::A);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOERROR);
+        assert!(reply.resources.is_empty());
+    }
+
+    #[test]
+    fn empty_non_terminals_block_wildcard() {
+        let zone = zone(&[
+            "*.example. 300 IN A 192.0.2.1",
+            "a.ent.example. 300 IN A 192.0.2.3",
+        ]);
+        // ent.example. exists without records, so it is no match for the wildcard
+        let reply = ask(&zone, "ent.example.", FlagRecordType::A);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NOERROR);
+        assert!(reply.resources.is_empty());
+
+        // Closest encloser is ent.example., which has no wildcard below it
+        let reply = ask(&zone, "b.ent.example.", FlagRecordType::A);
+        assert_eq!(reply.header.get_rcode().unwrap(), FlagRCode::NXDOMAIN);
+        assert!(reply.resources.is_empty());
+    }
+
+    #[test]
+    fn wildcard_cname_is_followed() {
+        let zone = zone(&[
+            "*.example. 300 IN CNAME www.example.",
+            "www.example. 300 IN A 192.0.2.2",
+        ]);
+        let reply = ask(&zone, "foo.example.", FlagRecordType::A);
+        assert_eq!(
+            answers(&reply),
+            [
+                "foo.example.\t300\tIN\tCNAME\twww.example.",
+                "www.example.\t300\tIN\tA\t192.0.2.2"
+            ]
+        );
+    }
+}